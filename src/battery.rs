@@ -0,0 +1,199 @@
+//! Traction battery model for the [`crate::emulator::VehicleEmulator`].
+//!
+//! Open circuit voltage follows a SoC curve, terminal voltage adds the drop across the internal
+//! resistance, and the charge/discharge current limits taper as the terminal voltage approaches
+//! the pack limits and derate with temperature.
+
+/// Pack model, SoC in % and currents positive into the battery
+#[derive(Debug, Clone, Copy)]
+pub struct Battery {
+    /// Open circuit voltage as `(SoC %, V)`, ascending SoC
+    pub ocv: &'static [(f32, f32)],
+    /// Pack internal resistance in Ω
+    pub internal_resistance: f32,
+    /// Usable capacity in Ah
+    pub capacity_ah: f32,
+    /// Highest terminal voltage while charging, reported in 102.1-2
+    pub charge_voltage: f32,
+    /// Lowest terminal voltage while discharging, reported in 200.4-5
+    pub discharge_voltage: f32,
+    /// Current limits before tapering and derating, in A
+    pub max_charge_current: f32,
+    pub max_discharge_current: f32,
+    /// Fraction of the current limits allowed as `(°C, 0.0-1.0)`, ascending temperature
+    pub derating: &'static [(f32, f32)],
+    /// Pack temperature in °C
+    pub temperature: f32,
+    soc: f32,
+}
+
+/// Linear interpolation over `curve`, clamped to its end points
+fn interpolate(curve: &[(f32, f32)], x: f32) -> f32 {
+    let (Some(first), Some(last)) = (curve.first(), curve.last()) else {
+        return 0.0;
+    };
+    if x <= first.0 {
+        return first.1;
+    }
+    for pair in curve.windows(2) {
+        let ((x0, y0), (x1, y1)) = (pair[0], pair[1]);
+        if x <= x1 {
+            return y0 + (y1 - y0) * (x - x0) / (x1 - x0);
+        }
+    }
+    last.1
+}
+
+impl Battery {
+    /// 24 kWh Nissan Leaf ZE0 pack (96s2p, 66 Ah) as seen in `CanLogs/`
+    pub fn leaf_ze0(soc: f32) -> Self {
+        const OCV: &[(f32, f32)] = &[
+            (0.0, 300.0),
+            (5.0, 330.0),
+            (10.0, 342.0),
+            (20.0, 355.0),
+            (30.0, 362.0),
+            (40.0, 368.0),
+            (50.0, 373.0),
+            (60.0, 378.0),
+            (70.0, 384.0),
+            (80.0, 390.0),
+            (90.0, 396.0),
+            (100.0, 403.0),
+        ];
+        const DERATING: &[(f32, f32)] = &[
+            (-20.0, 0.0),
+            (0.0, 0.25),
+            (10.0, 0.5),
+            (15.0, 1.0),
+            (40.0, 1.0),
+            (50.0, 0.5),
+            (60.0, 0.0),
+        ];
+        Self {
+            ocv: OCV,
+            internal_resistance: 0.1,
+            capacity_ah: 66.0,
+            charge_voltage: 410.0,
+            discharge_voltage: 300.0,
+            max_charge_current: 125.0,
+            max_discharge_current: 100.0,
+            derating: DERATING,
+            temperature: 25.0,
+            soc: soc.clamp(0.0, 100.0),
+        }
+    }
+
+    pub fn soc(&self) -> f32 {
+        self.soc
+    }
+
+    pub fn open_circuit_voltage(&self) -> f32 {
+        interpolate(self.ocv, self.soc)
+    }
+
+    /// Voltage at the pack terminals with `amps` flowing in, negative while discharging
+    pub fn terminal_voltage(&self, amps: f32) -> f32 {
+        self.open_circuit_voltage() + amps * self.internal_resistance
+    }
+
+    /// Nominal energy for 101.5-6 in kWh, taken at 50 % SoC
+    pub fn rated_capacity_kwh(&self) -> f32 {
+        interpolate(self.ocv, 50.0) * self.capacity_ah / 1000.0
+    }
+
+    fn derate(&self) -> f32 {
+        interpolate(self.derating, self.temperature).clamp(0.0, 1.0)
+    }
+
+    /// Highest current keeping the terminal voltage at or below `charge_voltage`
+    pub fn charge_current_limit(&self) -> f32 {
+        let headroom =
+            (self.charge_voltage - self.open_circuit_voltage()) / self.internal_resistance;
+        (self.max_charge_current * self.derate())
+            .min(headroom)
+            .max(0.0)
+    }
+
+    /// Highest current keeping the terminal voltage at or above `discharge_voltage`
+    pub fn discharge_current_limit(&self) -> f32 {
+        let headroom =
+            (self.open_circuit_voltage() - self.discharge_voltage) / self.internal_resistance;
+        (self.max_discharge_current * self.derate())
+            .min(headroom)
+            .max(0.0)
+    }
+
+    /// Integrate `amps` (negative while discharging) over `dt_ms`
+    pub fn step(&mut self, amps: f32, dt_ms: u64) {
+        let amp_hours = amps * dt_ms as f32 / 3_600_000.0;
+        self.soc = (self.soc + amp_hours / self.capacity_ah * 100.0).clamp(0.0, 100.0);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::emulator::{VehicleEmulator, VehicleParams};
+    use crate::evse::{Evse, EvseState};
+    use crate::sim::{EvseNode, SimBus, VehicleNode};
+    use crate::Chademo;
+
+    #[test]
+    fn battery_test() {
+        let mut battery = Battery::leaf_ze0(55.0);
+        assert_eq!(battery.open_circuit_voltage(), 375.5);
+        assert_eq!(battery.terminal_voltage(-50.0), 370.5);
+        assert!((battery.rated_capacity_kwh() - 24.6).abs() < 0.1);
+        assert_eq!(battery.charge_current_limit(), 125.0);
+        assert_eq!(battery.discharge_current_limit(), 100.0);
+
+        // One hour at 66 A is the whole pack
+        battery.step(33.0, 3_600_000);
+        assert_eq!(battery.soc(), 100.0);
+        assert!((battery.charge_current_limit() - 70.0).abs() < 0.01);
+        battery.step(-66.0, 3_600_000);
+        assert_eq!(battery.soc(), 0.0);
+        assert_eq!(battery.discharge_current_limit(), 0.0);
+
+        battery.step(66.0, 1_800_000);
+        battery.temperature = 5.0;
+        assert_eq!(battery.charge_current_limit(), 125.0 * 0.375);
+        battery.temperature = 60.0;
+        assert_eq!(battery.charge_current_limit(), 0.0);
+    }
+
+    #[test]
+    fn taper_test() {
+        // Closed loop from 92 %, the request follows the battery limit down towards full
+        let params = VehicleParams {
+            max_charging_current: 125,
+            target_soc: 100,
+            ..Default::default()
+        };
+        let ev = VehicleEmulator::with_battery(params, Battery::leaf_ze0(92.0));
+        let mut bus = SimBus::new(10);
+        let evse = bus.add(EvseNode::new(Evse::new(Chademo::new(125))));
+        let ev = bus.add(VehicleNode::new(ev));
+
+        assert!(bus.run_until(5_000, |bus| {
+            bus.node::<EvseNode>(evse).state() == EvseState::Charging
+        }));
+        bus.run_for(1_000);
+        let mut requests = vec![bus.node::<VehicleNode>(ev).ev.x102.charging_current_request];
+        while bus.node::<EvseNode>(evse).state() == EvseState::Charging {
+            bus.run_for(10_000);
+            requests.push(bus.node::<VehicleNode>(ev).ev.x102.charging_current_request);
+        }
+        assert_eq!(requests[0], 125);
+        assert!(requests.windows(2).all(|w| w[1] <= w[0]), "{requests:?}");
+        assert!(
+            requests.iter().any(|&amps| amps > 0 && amps < 100),
+            "{requests:?}"
+        );
+        let vehicle = &bus.node::<VehicleNode>(ev).ev;
+        assert_eq!(vehicle.soc(), 100.0);
+        assert_eq!(vehicle.x102.target_battery_voltage, 410.0);
+        assert_eq!(vehicle.x200.minimum_discharge_voltage, 300);
+    }
+}
@@ -0,0 +1,38 @@
+//! Check a `CanLogs/` CSV trace against the spec rules in `compliance`.
+//!
+//! `chademo-compliance <trace.csv>...` prints each violation and exits with 1 if any were found.
+mod common;
+use chademo_rs::compliance::ComplianceChecker;
+use chademo_rs::trace;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let paths: Vec<String> = std::env::args().skip(1).collect();
+    if paths.is_empty() {
+        eprintln!("usage: chademo-compliance <trace.csv>...");
+        std::process::exit(2);
+    }
+    let mut total = 0;
+    for path in paths {
+        let log = std::fs::read_to_string(&path)?;
+        let mut checker = ComplianceChecker::new();
+        for record in trace::records(&log) {
+            let record = record?;
+            let Some(id) = record.standard_id() else {
+                continue;
+            };
+            let result = checker.check(record.timestamp_ms(), id, record.data(), |violation| {
+                println!("{path}: {violation}")
+            });
+            // Traces may carry unrelated traffic
+            if let Err(e) = result {
+                eprintln!("{path}: {} ms: {e}", record.timestamp_ms());
+            }
+        }
+        total += checker.violations();
+    }
+    if total > 0 {
+        eprintln!("{total} violation(s)");
+        std::process::exit(1);
+    }
+    Ok(())
+}
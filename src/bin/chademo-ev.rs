@@ -0,0 +1,63 @@
+//! Vehicle emulator on a SocketCAN interface.
+//!
+//! `chademo-ev <ifname> [soc] [target_soc]`, the other side of `chademo-evse` on `vcan0`.
+mod common;
+use std::io::ErrorKind;
+use std::time::{Duration, Instant};
+
+use chademo_rs::emulator::{VehicleEmulator, VehicleParams, VehicleState};
+use chademo_rs::socketcan::{CanSocket, SocketCanFrame};
+use embedded_can::{Frame, Id, StandardId};
+
+const TX_PERIOD_MS: u64 = 100;
+
+fn main() -> std::io::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let Some(ifname) = args.next() else {
+        eprintln!("usage: chademo-ev <ifname> [soc] [target_soc]");
+        std::process::exit(2);
+    };
+    let soc = args.next().and_then(|a| a.parse().ok()).unwrap_or(50.0);
+    let mut params = VehicleParams::default();
+    if let Some(target_soc) = args.next().and_then(|a| a.parse().ok()) {
+        params.target_soc = target_soc;
+    }
+
+    let socket = CanSocket::open(&ifname)?;
+    socket.set_read_timeout(Duration::from_millis(10))?;
+    let mut ev = VehicleEmulator::new(params, soc);
+    let start = Instant::now();
+    let mut last_tx = None;
+    let mut last_print = 0;
+
+    loop {
+        let now = start.elapsed().as_millis() as u64;
+        match socket.read_frame() {
+            Ok(frame) => {
+                if let Id::Standard(id) = frame.id() {
+                    // Ignore other traffic on the bus
+                    let _ = ev.receive(id.as_raw(), frame.data());
+                }
+            }
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => (),
+            Err(e) => return Err(e),
+        }
+
+        ev.tick(now);
+        if last_tx.is_none_or(|tx| now - tx >= TX_PERIOD_MS) {
+            last_tx = Some(now);
+            for (id, data) in ev.tx_payloads() {
+                let frame = SocketCanFrame::new(StandardId::new(id).unwrap(), &data).unwrap();
+                socket.write_frame(&frame)?;
+            }
+        }
+        if now - last_print >= 1000 {
+            last_print = now;
+            println!("{:?} soc {:.1}%", ev.state(), ev.soc());
+        }
+        if ev.state() == VehicleState::Finished {
+            println!("{:?} soc {:.1}%", ev.state(), ev.soc());
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,71 @@
+//! EVSE loop on a SocketCAN interface with an ideal power stage.
+//!
+//! `chademo-evse <ifname> [max_amps]`, pair with `chademo-ev` on the same `vcan0` to bench test.
+mod common;
+use std::io::ErrorKind;
+use std::time::{Duration, Instant};
+
+use chademo_rs::eh1::ChademoFrames;
+use chademo_rs::evse::{Evse, EvseState};
+use chademo_rs::socketcan::{CanSocket, SocketCanFrame};
+use chademo_rs::Chademo;
+use embedded_can::{Frame, Id};
+
+fn main() -> std::io::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let Some(ifname) = args.next() else {
+        eprintln!("usage: chademo-evse <ifname> [max_amps]");
+        std::process::exit(2);
+    };
+    let max_amps = args.next().and_then(|a| a.parse().ok()).unwrap_or(16);
+
+    let socket = CanSocket::open(&ifname)?;
+    socket.set_read_timeout(Duration::from_millis(10))?;
+    let mut evse = Evse::new(Chademo::new(max_amps));
+    let start = Instant::now();
+    let mut output = (0.0, 0.0);
+    let mut last_print = 0;
+
+    loop {
+        let now = start.elapsed().as_millis() as u64;
+        match socket.read_frame() {
+            Ok(frame) => match frame.id() {
+                Id::Standard(id) => {
+                    if let Err(e) = evse.receive(now, id.as_raw(), frame.data()) {
+                        eprintln!("{e}");
+                    }
+                }
+                Id::Extended(_) => (),
+            },
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => (),
+            Err(e) => return Err(e),
+        }
+
+        let setpoint = evse.poll(now, output.0, output.1);
+        output = match setpoint.enabled {
+            true => (setpoint.voltage, setpoint.current),
+            false => (0.0, 0.0),
+        };
+        if evse.tx_due(now) {
+            for frame in evse
+                .chademo
+                .tx_frames::<SocketCanFrame>()
+                .into_iter()
+                .flatten()
+            {
+                socket.write_frame(&frame)?;
+            }
+        }
+        if now - last_print >= 1000 {
+            last_print = now;
+            println!("{:?} {}", evse.state(), evse.chademo);
+        }
+        if evse.state() == EvseState::Finished {
+            println!("{:?} {}", evse.state(), evse.chademo);
+            if let Some(session) = evse.session() {
+                println!("{session}");
+            }
+            return Ok(());
+        }
+    }
+}
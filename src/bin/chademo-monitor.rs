@@ -0,0 +1,65 @@
+//! Passive session view of a CAN trace or a live interface.
+//!
+//! `chademo-monitor <trace.csv>` replays a `CanLogs/` capture, `chademo-monitor <ifname>` listens
+//! on SocketCAN when built with the `socketcan` feature. Prints every phase change.
+mod common;
+use chademo_rs::monitor::{ChademoMonitor, Phase};
+use chademo_rs::trace;
+
+fn report(monitor: &ChademoMonitor, last: &mut Phase, timestamp_ms: u64) {
+    if monitor.phase() != *last {
+        *last = monitor.phase();
+        println!("{:>10} ms {}", timestamp_ms, monitor);
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let Some(source) = std::env::args().nth(1) else {
+        eprintln!("usage: chademo-monitor <trace.csv | ifname>");
+        std::process::exit(2);
+    };
+    let mut monitor = ChademoMonitor::new();
+    let mut last = Phase::Idle;
+
+    if std::path::Path::new(&source).is_file() {
+        let log = std::fs::read_to_string(&source)?;
+        for record in trace::records(&log) {
+            let record = record?;
+            let Some(id) = record.standard_id() else {
+                continue;
+            };
+            if let Err(e) = monitor.decode_payload(id, record.data()) {
+                eprintln!("{}: {e}", record.timestamp_ms());
+            }
+            report(&monitor, &mut last, record.timestamp_ms());
+        }
+        return Ok(());
+    }
+    live(&source, &mut monitor, &mut last)
+}
+
+#[cfg(feature = "socketcan")]
+fn live(
+    ifname: &str,
+    monitor: &mut ChademoMonitor,
+    last: &mut Phase,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use chademo_rs::eh1::MonitorFrames;
+    let socket = chademo_rs::socketcan::CanSocket::open(ifname)?;
+    let start = std::time::Instant::now();
+    loop {
+        let frame = socket.read_frame()?;
+        // Other traffic on the bus is expected when sniffing
+        let _ = monitor.decode(frame);
+        report(monitor, last, start.elapsed().as_millis() as u64);
+    }
+}
+
+#[cfg(not(feature = "socketcan"))]
+fn live(
+    ifname: &str,
+    _monitor: &mut ChademoMonitor,
+    _last: &mut Phase,
+) -> Result<(), Box<dyn std::error::Error>> {
+    Err(format!("{ifname} is not a file, live capture needs the socketcan feature").into())
+}
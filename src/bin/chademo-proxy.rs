@@ -0,0 +1,62 @@
+//! Sit between a vehicle and a charger on two SocketCAN interfaces and rewrite frames.
+//!
+//! `chademo-proxy <vehicle_if> <charger_if> [max_amps] [charge_limit_%]`, every rewrite is
+//! printed with the original and re-encoded payload.
+mod common;
+use std::io::ErrorKind;
+use std::time::{Duration, Instant};
+
+use chademo_rs::eh1::ProxyFrames;
+use chademo_rs::proxy::{
+    ChademoProxy, ClampChargingCurrent, Decoded, Forward, OverrideChargeLimit, Rewrite,
+};
+use chademo_rs::socketcan::CanSocket;
+
+fn main() -> std::io::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let [vehicle_if, charger_if, rest @ ..] = args.as_slice() else {
+        eprintln!("usage: chademo-proxy <vehicle_if> <charger_if> [max_amps] [charge_limit_%]");
+        std::process::exit(2);
+    };
+    let max_amps = rest.first().and_then(|a| a.parse().ok()).unwrap_or(u8::MAX);
+    // 0 leaves 200.7 as sent by the vehicle
+    let charge_limit: u8 = rest.get(1).and_then(|a| a.parse().ok()).unwrap_or(0);
+
+    let vehicle = CanSocket::open(vehicle_if)?;
+    let charger = CanSocket::open(charger_if)?;
+    for socket in [&vehicle, &charger] {
+        socket.set_read_timeout(Duration::from_millis(1))?;
+    }
+    let limit = move |frame: &mut Decoded| {
+        if charge_limit > 0 {
+            OverrideChargeLimit(charge_limit).rewrite(frame);
+        }
+    };
+    let mut proxy = ChademoProxy::new((ClampChargingCurrent(max_amps), limit));
+    let start = Instant::now();
+
+    loop {
+        for (from, to) in [(&vehicle, &charger), (&charger, &vehicle)] {
+            let frame = match from.read_frame() {
+                Ok(frame) => frame,
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    continue
+                }
+                Err(e) => return Err(e),
+            };
+            let frame = match proxy.forward_frame(frame) {
+                Ok((frame, Forward::Rewritten(log))) => {
+                    println!("{:>10} ms {log}", start.elapsed().as_millis());
+                    frame
+                }
+                Ok((frame, Forward::Unchanged)) => frame,
+                // Forward what the proxy cannot decode rather than dropping it
+                Err((frame, e)) => {
+                    eprintln!("{e}");
+                    frame
+                }
+            };
+            to.write_frame(&frame)?;
+        }
+    }
+}
@@ -0,0 +1,38 @@
+//! Run scenario files against the simulated EVSE, see `scenario` and `scenarios/`.
+//!
+//! `chademo-scenario <scenario.toml>...` prints PASS/FAIL per expectation and exits with 1 if
+//! any failed.
+mod common;
+use chademo_rs::scenario::Scenario;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let paths: Vec<String> = std::env::args().skip(1).collect();
+    if paths.is_empty() {
+        eprintln!("usage: chademo-scenario <scenario.toml>...");
+        std::process::exit(2);
+    }
+    let mut failed = 0;
+    for path in paths {
+        let text = std::fs::read_to_string(&path)?;
+        let scenario = match Scenario::from_toml(&text) {
+            Ok(scenario) => scenario,
+            Err(e) => {
+                eprintln!("{path}: {e}");
+                failed += 1;
+                continue;
+            }
+        };
+        let report = scenario.run();
+        print!("{report}");
+        failed += report
+            .outcomes
+            .iter()
+            .filter(|o| o.failure.is_some())
+            .count();
+    }
+    if failed > 0 {
+        eprintln!("{failed} failure(s)");
+        std::process::exit(1);
+    }
+    Ok(())
+}
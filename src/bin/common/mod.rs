@@ -0,0 +1,17 @@
+//! Shared by the host tools.
+
+/// The host tools have no defmt transport, library log events are dropped
+#[cfg(feature = "defmt")]
+mod discard {
+    #[defmt::global_logger]
+    struct Discard;
+
+    unsafe impl defmt::Logger for Discard {
+        fn acquire() {}
+        unsafe fn flush() {}
+        unsafe fn release() {}
+        unsafe fn write(_bytes: &[u8]) {}
+    }
+
+    defmt::timestamp!("");
+}
@@ -0,0 +1,237 @@
+//! Checks a frame stream against the normative rules in the [`crate::frames`] field docs.
+//!
+//! Feed every frame of a trace, in order, to [`ComplianceChecker::check`]; each rule broken is
+//! reported once per offending frame with its clause number and the frame time stamp.
+use crate::error::ChademoError;
+use crate::frames::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rule {
+    /// 102.5.7 set after the first frame, only 1 to 0 is allowed
+    DischargeCompatibleChanged,
+    /// 102.5.4 updated after the initial charging current request (102.3)
+    NormalStopRequestChanged,
+    /// 109.5.5 cleared and 109.5.0 set in different frames
+    StopControlNotExclusive,
+    /// 109.5.0 cleared while present output current (109.3) is above 5 A
+    StationClearedAboveStopCurrent,
+    /// 108.1-2 updated after the first frame
+    AvailableOutputVoltageChanged,
+    /// 200.4-5 updated once set
+    MinimumDischargeVoltageChanged,
+}
+
+impl Rule {
+    pub fn clause(&self) -> &'static str {
+        use Rule::*;
+        match self {
+            DischargeCompatibleChanged => "102.5.7",
+            NormalStopRequestChanged => "102.5.4",
+            StopControlNotExclusive => "109.5.5",
+            StationClearedAboveStopCurrent => "109.5.0",
+            AvailableOutputVoltageChanged => "108.1-2",
+            MinimumDischargeVoltageChanged => "200.4-5",
+        }
+    }
+}
+
+impl core::fmt::Display for Rule {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        use Rule::*;
+        let text = match self {
+            DischargeCompatibleChanged => "discharge compatible flag set after first send",
+            NormalStopRequestChanged => "stop request updated after initial current request",
+            StopControlNotExclusive => "stop control cleared apart from charger status set",
+            StationClearedAboveStopCurrent => "charger status cleared above 5 A",
+            AvailableOutputVoltageChanged => "available output voltage updated",
+            MinimumDischargeVoltageChanged => "minimum discharge voltage updated once set",
+        };
+        write!(f, "{} {}", self.clause(), text)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Violation {
+    pub timestamp_ms: u64,
+    pub rule: Rule,
+}
+
+impl core::fmt::Display for Violation {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "{} ms: {}", self.timestamp_ms, self.rule)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ComplianceChecker {
+    x102: Option<X102>,
+    x108: Option<X108>,
+    x109: Option<X109>,
+    x200: Option<X200>,
+    /// 102.3 has been non-zero
+    current_requested: bool,
+    violations: usize,
+}
+
+impl ComplianceChecker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of violations reported so far
+    pub fn violations(&self) -> usize {
+        self.violations
+    }
+
+    /// Check one raw frame from either side, `report` is called for each rule it breaks
+    pub fn check(
+        &mut self,
+        timestamp_ms: u64,
+        id: u16,
+        data: &[u8],
+        mut report: impl FnMut(Violation),
+    ) -> Result<(), ChademoError> {
+        let mut broken = |rule| {
+            self.violations += 1;
+            report(Violation { timestamp_ms, rule })
+        };
+        match id {
+            X102::ID => {
+                let x102 = X102::from_payload(data)?;
+                if let Some(prev) = self.x102 {
+                    if !prev.status.status_discharge_compatible
+                        && x102.status.status_discharge_compatible
+                    {
+                        broken(Rule::DischargeCompatibleChanged);
+                    }
+                    if self.current_requested
+                        && prev.status.status_normal_stop_request
+                            != x102.status.status_normal_stop_request
+                    {
+                        broken(Rule::NormalStopRequestChanged);
+                    }
+                }
+                self.current_requested |= x102.charging_current_request > 0;
+                self.x102 = Some(x102);
+            }
+            X108::ID => {
+                let x108 = X108::from_payload(data)?;
+                if let Some(prev) = self.x108 {
+                    if prev.avaible_output_voltage != x108.avaible_output_voltage {
+                        broken(Rule::AvailableOutputVoltageChanged);
+                    }
+                }
+                self.x108 = Some(x108);
+            }
+            X109::ID => {
+                let x109 = X109::from_payload(data)?;
+                if let Some(prev) = self.x109 {
+                    let (before, now) = (prev.status, x109.status);
+                    let stop_cleared =
+                        before.status_charger_stop_control && !now.status_charger_stop_control;
+                    let station_set = !before.status_station && now.status_station;
+                    if stop_cleared != station_set {
+                        broken(Rule::StopControlNotExclusive);
+                    }
+                    if before.status_station && !now.status_station && x109.output_current > 5 {
+                        broken(Rule::StationClearedAboveStopCurrent);
+                    }
+                }
+                self.x109 = Some(x109);
+            }
+            X200::ID => {
+                let x200 = X200::from_payload(data)?;
+                if let Some(prev) = self.x200 {
+                    if prev.minimum_discharge_voltage != 0
+                        && prev.minimum_discharge_voltage != x200.minimum_discharge_voltage
+                    {
+                        broken(Rule::MinimumDischargeVoltageChanged);
+                    }
+                }
+                self.x200 = Some(x200);
+            }
+            X100::ID | X101::ID | X208::ID | X209::ID => (),
+            bad_id => return Err(ChademoError::DecodeBadId(bad_id)),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::trace;
+    use std::vec::Vec;
+
+    fn run(frames: &[(u64, u16, [u8; 8])]) -> Vec<Violation> {
+        let mut checker = ComplianceChecker::new();
+        let mut found = Vec::new();
+        for (ts, id, data) in frames {
+            checker.check(*ts, *id, data, |v| found.push(v)).unwrap();
+        }
+        assert_eq!(checker.violations(), found.len());
+        found
+    }
+
+    #[test]
+    fn capture_test() {
+        let log = include_str!("../CanLogs/nissan-leaf-chademo-start-stop.csv");
+        let frames: Vec<_> = trace::records(log)
+            .map(|r| r.unwrap())
+            .map(|r| (r.timestamp_ms(), r.standard_id().unwrap(), r.data))
+            .collect();
+        assert_eq!(run(&frames), []);
+    }
+
+    #[test]
+    fn rules_test() {
+        let x102 = |status: u8, current: u8| [2, 0x9A, 1, current, 0, status, 50, 0];
+        let found = run(&[
+            (0, 0x102, x102(0x01, 0)),
+            (100, 0x102, x102(0x81, 0)),
+            (200, 0x102, x102(0x91, 0)),
+            (300, 0x102, x102(0x01, 10)),
+            (400, 0x102, x102(0x11, 10)),
+        ]);
+        assert_eq!(
+            found,
+            [
+                Violation {
+                    timestamp_ms: 100,
+                    rule: Rule::DischargeCompatibleChanged
+                },
+                Violation {
+                    timestamp_ms: 400,
+                    rule: Rule::NormalStopRequestChanged
+                },
+            ]
+        );
+
+        let x109 = |status: u8, current: u8| [2, 0x76, 1, current, 1, status, 0xFF, 0x3C];
+        let found = run(&[
+            (0, 0x109, x109(0x24, 0)),
+            (100, 0x109, x109(0x04, 0)),
+            (200, 0x109, x109(0x05, 10)),
+            (300, 0x109, x109(0x25, 10)),
+            (400, 0x109, x109(0x24, 8)),
+        ]);
+        let rules: Vec<_> = found.iter().map(|v| v.rule.clause()).collect();
+        assert_eq!(rules, ["109.5.5", "109.5.5", "109.5.0"]);
+        assert_eq!(
+            found[2].to_string(),
+            "400 ms: 109.5.0 charger status cleared above 5 A"
+        );
+
+        let x200 = |volts: u16| {
+            let [lo, hi] = volts.to_le_bytes();
+            [0xFF, 0, 0, 0, lo, hi, 0xFF, 0]
+        };
+        let found = run(&[
+            (0, 0x200, x200(0)),
+            (100, 0x200, x200(250)),
+            (200, 0x200, x200(260)),
+        ]);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].rule, Rule::MinimumDischargeVoltageChanged);
+    }
+}
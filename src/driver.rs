@@ -0,0 +1,329 @@
+//! Async EVSE loop for embassy and other executors.
+//!
+//! [`Driver::run`] owns an [`Evse`] and drives it from CAN, a [`Clock`] and the [`PowerStage`]:
+//! vehicle frames go to [`Evse::receive`], the supervision timers are serviced every
+//! [`Driver::POLL_MS`] and the EVSE frames go out every [`Evse::TX_PERIOD_MS`].
+//! Other tasks steer it with [`Driver::send`] and follow it with [`Driver::status`].
+use core::convert::Infallible;
+
+use embassy_futures::select::{select3, Either3};
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::channel::Channel;
+use embassy_sync::watch::{Receiver, Watch};
+use embedded_can::{Frame, Id};
+
+use crate::eh1::ChademoFrames;
+use crate::error::StopReason;
+use crate::evse::{Evse, EvseState, PowerStage, Setpoint};
+
+/// Receiving half of the CAN bus, `receive` must be cancel safe
+#[allow(async_fn_in_trait)]
+pub trait CanRx {
+    type Frame: Frame;
+    type Error;
+    async fn receive(&mut self) -> Result<Self::Frame, Self::Error>;
+}
+
+/// Transmitting half of the CAN bus
+#[allow(async_fn_in_trait)]
+pub trait CanTx {
+    type Frame: Frame;
+    type Error;
+    async fn transmit(&mut self, frame: &Self::Frame) -> Result<(), Self::Error>;
+}
+
+/// Monotonic time in ms, e.g. `embassy_time::Instant` and `Timer::at`
+#[allow(async_fn_in_trait)]
+pub trait Clock {
+    fn now_ms(&self) -> u64;
+    /// Wait until [`Self::now_ms`] reaches `deadline_ms`
+    async fn wait_until(&mut self, deadline_ms: u64);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Command {
+    /// Begin a session, H'108/H'109 are sent from here on
+    ///
+    /// Ignored while a session runs, after [`EvseState::Finished`] the next one starts from
+    /// the [`Evse`] handed to [`Driver::run`].
+    Start,
+    /// Stop the running session, see [`Evse::stop`]
+    Stop,
+    /// Charging current limit and V2H discharge request in A
+    ///
+    /// See [`crate::Chademo::set_charge_current_limit`], the limit applies to the output in any
+    /// state while 108.3 only follows it before charging or under dynamic control.
+    SetLimits {
+        charge_amps: u8,
+        discharge_amps: f32,
+    },
+}
+
+/// Published on every change through [`Driver::status`]
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Status {
+    /// `None` until the first [`Command::Start`]
+    pub state: Option<EvseState>,
+    pub setpoint: Setpoint,
+    /// Output measured by the power stage, current negative while discharging
+    pub voltage: f32,
+    pub current: f32,
+    /// 102.6
+    pub soc: u8,
+    pub stop_reason: Option<StopReason>,
+}
+
+#[derive(Debug)]
+pub enum Error<R, T> {
+    Rx(R),
+    Tx(T),
+}
+
+/// Command channel and status watch shared between [`Driver::run`] and the application
+///
+/// Holds up to `N` queued commands and `N` status receivers, usually placed in a `static`.
+pub struct Driver<M: RawMutex, const N: usize = 4> {
+    commands: Channel<M, Command, N>,
+    status: Watch<M, Status, N>,
+}
+
+impl<M: RawMutex, const N: usize> Default for Driver<M, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<M: RawMutex, const N: usize> Driver<M, N> {
+    /// Period of [`Evse::poll`] and the power stage update
+    pub const POLL_MS: u64 = 10;
+
+    pub const fn new() -> Self {
+        Self {
+            commands: Channel::new(),
+            status: Watch::new(),
+        }
+    }
+
+    pub async fn send(&self, command: Command) {
+        self.commands.send(command).await
+    }
+
+    /// `None` once `N` receivers exist
+    pub fn status(&self) -> Option<Receiver<'_, M, Status, N>> {
+        self.status.receiver()
+    }
+
+    /// Run sessions of `evse` until the CAN bus fails
+    pub async fn run<RX, TX, C, P>(
+        &self,
+        evse: Evse,
+        mut can_rx: RX,
+        mut can_tx: TX,
+        mut clock: C,
+        mut io: P,
+    ) -> Result<Infallible, Error<RX::Error, TX::Error>>
+    where
+        RX: CanRx,
+        TX: CanTx,
+        C: Clock,
+        P: PowerStage,
+    {
+        let mut template = evse;
+        let mut evse = template.clone();
+        let mut running = false;
+        let mut output = (0.0, 0.0);
+        let mut next_poll = clock.now_ms();
+        self.publish(Status::default());
+
+        loop {
+            let event = select3(
+                can_rx.receive(),
+                self.commands.receive(),
+                clock.wait_until(next_poll),
+            );
+            match event.await {
+                Either3::First(frame) => {
+                    let frame = frame.map_err(Error::Rx)?;
+                    if let (true, Id::Standard(id)) = (running, frame.id()) {
+                        // Decode errors are logged and left to the receive timeout
+                        let _ = evse.receive(clock.now_ms(), id.as_raw(), frame.data());
+                    }
+                }
+                Either3::Second(Command::Start) => {
+                    if !running {
+                        info!("session start");
+                        evse = template.clone();
+                        running = true;
+                    }
+                }
+                Either3::Second(Command::Stop) => evse.stop(),
+                Either3::Second(Command::SetLimits {
+                    charge_amps,
+                    discharge_amps,
+                }) => {
+                    // Kept for the following sessions too
+                    for evse in [&mut template, &mut evse] {
+                        evse.chademo.set_charge_current_limit(charge_amps);
+                        evse.set_discharge_request(discharge_amps);
+                    }
+                }
+                Either3::Third(()) => {
+                    let now = clock.now_ms();
+                    next_poll = (next_poll + Self::POLL_MS).max(now);
+                    if !running {
+                        continue;
+                    }
+                    let setpoint = evse.poll(now, output.0, output.1);
+                    io.apply(now, setpoint);
+                    output = io.measured();
+                    if evse.tx_due(now) {
+                        for frame in evse.chademo.tx_frames::<TX::Frame>().into_iter().flatten() {
+                            can_tx.transmit(&frame).await.map_err(Error::Tx)?;
+                        }
+                    }
+                    running = evse.state() != EvseState::Finished;
+                    self.publish(Status {
+                        state: Some(evse.state()),
+                        setpoint,
+                        voltage: output.0,
+                        current: output.1,
+                        soc: evse.chademo.x102.state_of_charge,
+                        stop_reason: evse.chademo.stop_reason(),
+                    });
+                }
+            }
+        }
+    }
+
+    fn publish(&self, status: Status) {
+        self.status.sender().send_if_modified(|old| {
+            let changed = *old != Some(status);
+            *old = Some(status);
+            changed
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use core::cell::RefCell;
+    use std::collections::VecDeque;
+
+    use embassy_futures::select::{select, Either};
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use crate::emulator::{VehicleEmulator, VehicleParams, VehicleState};
+    use crate::interface::ChademoCanFrame;
+    use crate::sim::SimPowerStage;
+    use crate::Chademo;
+
+    /// Virtual time and an emulated vehicle behind the CAN bus
+    struct Bench {
+        now_ms: u64,
+        ev: VehicleEmulator,
+        rx: VecDeque<(u16, [u8; 8])>,
+    }
+
+    struct Bus<'a>(&'a RefCell<Bench>);
+
+    impl CanRx for Bus<'_> {
+        type Frame = ChademoCanFrame;
+        type Error = Infallible;
+        async fn receive(&mut self) -> Result<ChademoCanFrame, Infallible> {
+            let Some((id, data)) = self.0.borrow_mut().rx.pop_front() else {
+                return core::future::pending().await;
+            };
+            let id = embedded_can::StandardId::new(id).unwrap();
+            Ok(ChademoCanFrame::new(id, &data).unwrap())
+        }
+    }
+
+    impl CanTx for Bus<'_> {
+        type Frame = ChademoCanFrame;
+        type Error = Infallible;
+        async fn transmit(&mut self, frame: &ChademoCanFrame) -> Result<(), Infallible> {
+            let Id::Standard(id) = frame.id() else {
+                unreachable!()
+            };
+            let _ = self.0.borrow_mut().ev.receive(id.as_raw(), frame.data());
+            Ok(())
+        }
+    }
+
+    impl Clock for Bus<'_> {
+        fn now_ms(&self) -> u64 {
+            self.0.borrow().now_ms
+        }
+        async fn wait_until(&mut self, deadline_ms: u64) {
+            {
+                let mut bench = self.0.borrow_mut();
+                assert!(
+                    bench.now_ms < 60_000,
+                    "stuck with the vehicle {:?}",
+                    bench.ev.state()
+                );
+                while bench.now_ms < deadline_ms {
+                    bench.now_ms += 10;
+                    let now = bench.now_ms;
+                    bench.ev.tick(now);
+                    if now.is_multiple_of(100) {
+                        let frames = bench.ev.tx_payloads();
+                        bench.rx.extend(frames);
+                    }
+                }
+            }
+            embassy_futures::yield_now().await;
+        }
+    }
+
+    #[test]
+    fn driver_test() {
+        // Protocol 1.0, 108.3 is fixed once charging
+        let bench = RefCell::new(Bench {
+            now_ms: 0,
+            ev: VehicleEmulator::new(VehicleParams::default(), 50.0),
+            rx: VecDeque::new(),
+        });
+        let driver: Driver<NoopRawMutex> = Driver::new();
+        let evse = Evse::new(Chademo::new(16));
+        let run = driver.run(
+            evse,
+            Bus(&bench),
+            Bus(&bench),
+            Bus(&bench),
+            SimPowerStage::ideal(),
+        );
+        let control = async {
+            let mut status = driver.status().unwrap();
+            assert_eq!(status.get().await.state, None);
+            let limit = |charge_amps| Command::SetLimits {
+                charge_amps,
+                discharge_amps: 0.0,
+            };
+            // Sent before the session, the vehicle sees it in 108.3
+            driver.send(limit(10)).await;
+            driver.send(Command::Start).await;
+            let charging = status
+                .changed_and(|s| s.state == Some(EvseState::Charging) && s.current > 0.0)
+                .await;
+            assert_eq!(charging.setpoint.current, 10.0);
+            let x108 = bench.borrow().ev.x108.unwrap();
+            assert_eq!(x108.available_output_current, 10);
+            // Lowered while charging it still caps the output
+            driver.send(limit(8)).await;
+            let limited = status.changed_and(|s| s.setpoint.current == 8.0).await;
+            assert_eq!(limited.state, Some(EvseState::Charging));
+            driver.send(Command::Stop).await;
+            status
+                .changed_and(|s| s.state == Some(EvseState::Finished))
+                .await
+        };
+        let Either::Second(finished) = embassy_futures::block_on(select(run, control));
+        assert_eq!(finished.stop_reason, Some(StopReason::User));
+        assert_eq!(finished.soc, 50);
+        assert_eq!(bench.borrow().ev.state(), VehicleState::Finished);
+    }
+}
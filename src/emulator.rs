@@ -0,0 +1,249 @@
+//! Vehicle side of a CHAdeMO session, for bench tests without a car.
+//!
+//! Answers the EVSE frames H'108/H'109/H'208/H'209 with H'100/H'101/H'102/H'200 following
+//! the vehicle half of the charging sequence.
+use crate::battery::Battery;
+use crate::error::ChademoError;
+use crate::frames::*;
+
+/// Fixed vehicle parameters, defaults approximate the Nissan Leaf ZE0 in `CanLogs/`
+#[derive(Debug, Clone, Copy)]
+pub struct VehicleParams {
+    /// 102.0
+    pub protocol: u8,
+    /// 100.2-3
+    pub minimum_battery_voltage: f32,
+    /// 100.4-5
+    pub maximum_battery_voltage: f32,
+    /// 102.1-2
+    pub target_battery_voltage: f32,
+    /// Highest charging current request (102.3)
+    pub max_charging_current: u8,
+    /// 101.1-2 in seconds
+    pub max_charging_time: u32,
+    /// 101.5-6 in kWh
+    pub rated_battery_capacity: f32,
+    /// 102.5.7
+    pub discharge_compatible: bool,
+    /// 200.0, sent once the contactors are closed
+    pub max_discharge_current: u8,
+    /// 200.6 in %, the vehicle stops discharging at this SoC
+    pub min_discharge_soc: u8,
+    /// SoC at which the vehicle asks for a normal stop (102.5.0 cleared)
+    pub target_soc: u8,
+}
+
+impl Default for VehicleParams {
+    fn default() -> Self {
+        Self {
+            protocol: 2,
+            minimum_battery_voltage: 0.0,
+            maximum_battery_voltage: 435.0,
+            target_battery_voltage: 410.0,
+            max_charging_current: 14,
+            max_charging_time: 60 * 60,
+            rated_battery_capacity: 22.2,
+            discharge_compatible: true,
+            max_discharge_current: 0,
+            min_discharge_soc: 0,
+            target_soc: 80,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum VehicleState {
+    /// Waiting for the first EVSE frame, 102.5.0 is 0
+    #[default]
+    Initialising,
+    /// 102.5.0 set, waiting for the connector lock (109.5.2)
+    Ready,
+    /// Contactors closed (102.5.3 = 0), waiting for 109.5.0
+    ContactorsClosed,
+    /// Requesting current (102.3)
+    Charging,
+    /// Stop requested by either side, waiting for output to cease
+    Stopping,
+    /// Contactors open again
+    Finished,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct VehicleEmulator {
+    pub params: VehicleParams,
+    pub x100: X100,
+    pub x101: X101,
+    pub x102: X102,
+    pub x200: X200,
+    /// Last frames received from the EVSE
+    pub x108: Option<X108>,
+    pub x109: Option<X109>,
+    pub x208: Option<X208>,
+    pub x209: Option<X209>,
+    /// Pack model driving SoC, 102.3 and 200.0 when set
+    pub battery: Option<Battery>,
+    state: VehicleState,
+    soc: f32,
+    last_tick_ms: Option<u64>,
+}
+
+impl VehicleEmulator {
+    pub fn new(params: VehicleParams, soc: f32) -> Self {
+        let mut x100 = X100::default();
+        x100.minimum_battery_voltage = params.minimum_battery_voltage;
+        x100.maximum_battery_voltage = params.maximum_battery_voltage;
+        x100.constant_of_charging_rate_indication = 100;
+
+        let mut x102 = X102::default();
+        x102.control_protocol_number_ev = params.protocol;
+        x102.target_battery_voltage = params.target_battery_voltage;
+        x102.status.status_discharge_compatible = params.discharge_compatible;
+        x102.status.status_vehicle = true;
+        x102.state_of_charge = soc as u8;
+
+        let mut x200 = X200::default();
+        x200.minimum_battery_discharge_level = params.min_discharge_soc;
+
+        Self {
+            params,
+            x100,
+            x101: X101::new(params.max_charging_time, 0, params.rated_battery_capacity),
+            x102,
+            x200,
+            x108: None,
+            x109: None,
+            x208: None,
+            x209: None,
+            battery: None,
+            state: VehicleState::Initialising,
+            soc,
+            last_tick_ms: None,
+        }
+    }
+
+    /// Vehicle backed by `battery`: 102.1-2, 101.5-6 and 200.4-5 come from the pack and the
+    /// current requests follow its limits while charging
+    pub fn with_battery(params: VehicleParams, battery: Battery) -> Self {
+        let params = VehicleParams {
+            target_battery_voltage: battery.charge_voltage,
+            rated_battery_capacity: battery.rated_capacity_kwh(),
+            ..params
+        };
+        let mut ev = Self::new(params, battery.soc());
+        ev.x200.minimum_discharge_voltage = battery.discharge_voltage as u16;
+        ev.battery = Some(battery);
+        ev
+    }
+
+    /// Decode a raw EVSE frame
+    pub fn receive(&mut self, id: u16, data: &[u8]) -> Result<(), ChademoError> {
+        match id {
+            X108::ID => self.x108 = Some(X108::from_payload(data)?),
+            X109::ID => self.x109 = Some(X109::from_payload(data)?),
+            X208::ID => self.x208 = Some(X208::from_payload(data)?),
+            X209::ID => self.x209 = Some(X209::from_payload(data)?),
+            bad_id => return Err(ChademoError::DecodeBadId(bad_id)),
+        };
+        Ok(())
+    }
+
+    /// Advance the vehicle sequence and integrate SoC from the EVSE present output (109.1-3)
+    /// and discharge current (208.0)
+    pub fn tick(&mut self, now_ms: u64) {
+        let dt_ms = now_ms.saturating_sub(self.last_tick_ms.unwrap_or(now_ms));
+        self.last_tick_ms = Some(now_ms);
+        let Some(x109) = self.x109 else {
+            return;
+        };
+        let discharge = self.x208.map_or(0, |x208| x208.get_discharge_current());
+        if self.x102.status.status_vehicle_charging {
+            let amps = x109.output_current as f32 - discharge as f32;
+            match &mut self.battery {
+                Some(battery) => {
+                    battery.step(amps, dt_ms);
+                    self.soc = battery.soc();
+                }
+                None => {
+                    let watt_hours = x109.output_voltage * amps * dt_ms as f32 / 3_600_000.0;
+                    self.soc += watt_hours / (self.params.rated_battery_capacity * 10.0);
+                    self.soc = self.soc.clamp(0.0, 100.0);
+                }
+            }
+            self.x102.state_of_charge = self.soc as u8;
+        }
+
+        use VehicleState::*;
+        self.state = match self.state {
+            Initialising => {
+                self.x102.status.status_vehicle_charging = true;
+                Ready
+            }
+            Ready | ContactorsClosed if x109.status.fault() => self.stop(),
+            Ready if x109.status.status_vehicle_connector_lock => {
+                self.x102.status.status_vehicle = false;
+                ContactorsClosed
+            }
+            ContactorsClosed if x109.status.status_station => {
+                self.x102.charging_current_request = self.params.max_charging_current;
+                self.x200.maximum_discharge_current = self.params.max_discharge_current;
+                Charging
+            }
+            Charging if x109.status.status_charger_stop_control => self.stop(),
+            Charging if discharge > 0 && self.soc <= self.params.min_discharge_soc as f32 => {
+                self.stop()
+            }
+            Charging if discharge == 0 && self.soc >= self.params.target_soc as f32 => self.stop(),
+            Stopping
+                if x109.output_current <= 5 && discharge <= 5 && !x109.status.status_station =>
+            {
+                self.x102.status.status_vehicle = true;
+                Finished
+            }
+            state => state,
+        };
+        if self.state == Charging {
+            self.apply_battery_limits();
+        }
+    }
+
+    /// Follow the pack current limits in 102.3 and 200.0, capped by the vehicle parameters
+    fn apply_battery_limits(&mut self) {
+        let Some(battery) = self.battery else {
+            return;
+        };
+        let charge = battery.charge_current_limit() as u8;
+        let discharge = battery.discharge_current_limit() as u8;
+        self.x102.charging_current_request = charge.min(self.params.max_charging_current);
+        self.x200.maximum_discharge_current = discharge.min(self.params.max_discharge_current);
+    }
+
+    /// Normal stop from the vehicle: drop the current request and clear 102.5.0
+    pub fn stop(&mut self) -> VehicleState {
+        self.x102.charging_current_request = 0;
+        self.x200.maximum_discharge_current = 0;
+        self.x102.status.status_vehicle_charging = false;
+        self.state = match self.state {
+            VehicleState::Finished => VehicleState::Finished,
+            _ => VehicleState::Stopping,
+        };
+        self.state
+    }
+
+    pub fn state(&self) -> VehicleState {
+        self.state
+    }
+
+    pub fn soc(&self) -> f32 {
+        self.soc
+    }
+
+    /// Raw vehicle frames as `(id, data)`
+    pub fn tx_payloads(&self) -> [(u16, [u8; 8]); 4] {
+        [
+            (X100::ID, self.x100.to_payload()),
+            (X101::ID, self.x101.to_payload()),
+            (X102::ID, self.x102.to_payload()),
+            (X200::ID, self.x200.to_payload()),
+        ]
+    }
+}
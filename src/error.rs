@@ -1,15 +1,93 @@
 #[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum ChademoError {
     DecodeBadId(u16),
     DecodeBadIdExt,
+    DecodeBadDlc(usize),
+    IncompatibleProtocol(u8),
+    TraceParse(usize),
+    EncodeFrame(u16),
 }
 impl core::error::Error for ChademoError {}
 impl core::fmt::Display for ChademoError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         use ChademoError::*;
         match self {
             DecodeBadId(id) => write!(f, "Attemtped to decode invalid CAN ID {id}"),
             DecodeBadIdExt => write!(f, "Attemtped to decode invalid extended CAN ID"),
+            DecodeBadDlc(dlc) => write!(f, "Attemtped to decode CAN frame with DLC {dlc}"),
+            IncompatibleProtocol(n) => write!(f, "Unsupported CHAdeMO protocol number {n}"),
+            TraceParse(col) => write!(f, "Invalid CAN trace field in column {col}"),
+            EncodeFrame(id) => write!(f, "Could not build CAN frame for ID {id:#X}"),
+        }
+    }
+}
+
+/// Why a session went to stop control (109.5.5)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum StopReason {
+    /// 102.4 battery fault bits as sent
+    BatteryFault(u8),
+    /// 102.5.2 vehicle charging system malfunction
+    VehicleChargingSystemFault,
+    /// 102.5.1 shift lever out of park
+    ShifterPosition,
+    /// 102.5.4 normal stop request from the vehicle
+    NormalStopRequest,
+    /// 102.5.0 cleared, the vehicle no longer permits charging
+    ChargingPermissionWithdrawn,
+    /// No valid H'102 within the receive timeout
+    Timeout,
+    /// 102.0 protocol number not supported (109.5.3)
+    IncompatibleProtocol(u8),
+    /// 102.1-2 target voltage above the available output voltage 108.1-2 (109.5.3)
+    BatteryIncompatible,
+    /// Maximum charging time 101.1-2 elapsed
+    MaxChargingTime,
+    /// Stop requested by the user or the application
+    User,
+    /// 109.5.1 charger malfunction
+    StationFault,
+}
+impl StopReason {
+    /// Frame and bit that caused the stop, `None` for causes outside the CAN frames
+    pub fn clause(&self) -> Option<&'static str> {
+        use StopReason::*;
+        match self {
+            BatteryFault(_) => Some("102.4"),
+            VehicleChargingSystemFault => Some("102.5.2"),
+            ShifterPosition => Some("102.5.1"),
+            NormalStopRequest => Some("102.5.4"),
+            ChargingPermissionWithdrawn => Some("102.5.0"),
+            Timeout => None,
+            IncompatibleProtocol(_) => Some("102.0"),
+            BatteryIncompatible => Some("102.1-2"),
+            MaxChargingTime => Some("101.1-2"),
+            User => None,
+            StationFault => Some("109.5.1"),
+        }
+    }
+}
+impl core::fmt::Display for StopReason {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        use StopReason::*;
+        if let Some(clause) = self.clause() {
+            write!(f, "{clause} ")?;
+        }
+        match self {
+            BatteryFault(bits) => write!(f, "battery fault {bits:#04X}"),
+            VehicleChargingSystemFault => write!(f, "vehicle charging system fault"),
+            ShifterPosition => write!(f, "shift lever out of park"),
+            NormalStopRequest => write!(f, "normal stop request"),
+            ChargingPermissionWithdrawn => write!(f, "charging permission withdrawn"),
+            Timeout => write!(f, "vehicle communication timeout"),
+            IncompatibleProtocol(n) => write!(f, "unsupported protocol number {n}"),
+            BatteryIncompatible => write!(f, "target voltage above available output"),
+            MaxChargingTime => write!(f, "maximum charging time elapsed"),
+            User => write!(f, "stopped by user"),
+            StationFault => write!(f, "charger malfunction"),
         }
     }
 }
@@ -0,0 +1,163 @@
+//! Typed changes of the vehicle state, queued by [`Chademo::decode_payload`].
+//!
+//! Applications drain [`Chademo::events`] after decoding instead of comparing getters with
+//! their previous values. The queue keeps the last [`crate::EVENT_QUEUE`] events.
+use crate::error::StopReason;
+use crate::frames::X102;
+#[cfg(doc)]
+use crate::Chademo;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Event {
+    /// 102.5.0 set, the vehicle permits charging
+    VehicleReady,
+    /// 102.5.3 cleared, the vehicle contactors closed
+    ContactorsClosed,
+    /// 102.5.3 set again after [`Event::ContactorsClosed`]
+    ContactorsOpened,
+    /// 102.3 charging current request changed, in A
+    ChargeRequestChanged(u8),
+    /// Vehicle request to stop while it permitted charging, see [`X102::stop_reason`]
+    StopRequested(StopReason),
+    /// 102.4 fault bits as sent, on every newly set bit
+    FaultRaised(u8),
+    /// 102.4 back to all clear
+    FaultCleared,
+    /// Discharge limit (200.0) no longer masked and above 0, in A
+    DischargePermitted(u8),
+    /// Discharge limit back to 0 or masked
+    DischargeWithdrawn,
+    /// 102.6 state of charge changed, in %
+    SocChanged(u8),
+}
+
+impl Event {
+    /// Events between two H'102, `previous` is `None` for the first one received
+    pub(crate) fn x102_changes(previous: Option<&X102>, x102: &X102, mut emit: impl FnMut(Event)) {
+        let (ready, closed, request, stop, faults, soc) = match previous {
+            Some(p) => (
+                p.car_ready(),
+                p.contactors_closed(),
+                p.charging_current_request,
+                p.stop_reason(),
+                p.faults().into(),
+                Some(p.state_of_charge),
+            ),
+            None => (
+                false,
+                false,
+                0,
+                Some(StopReason::ChargingPermissionWithdrawn),
+                0,
+                None,
+            ),
+        };
+        if x102.car_ready() && !ready {
+            emit(Event::VehicleReady);
+        }
+        match (closed, x102.contactors_closed()) {
+            (false, true) => emit(Event::ContactorsClosed),
+            (true, false) => emit(Event::ContactorsOpened),
+            _ => {}
+        }
+        let bits: u8 = x102.faults().into();
+        if bits & !faults != 0 {
+            emit(Event::FaultRaised(bits));
+        } else if bits == 0 && faults != 0 {
+            emit(Event::FaultCleared);
+        }
+        if let (None, Some(reason)) = (stop, x102.stop_reason()) {
+            emit(Event::StopRequested(reason));
+        }
+        if x102.charging_current_request != request {
+            emit(Event::ChargeRequestChanged(x102.charging_current_request));
+        }
+        if soc != Some(x102.state_of_charge) {
+            emit(Event::SocChanged(x102.state_of_charge));
+        }
+    }
+
+    /// Change of [`Chademo::requested_discharging_amps`] across a decoded frame
+    pub(crate) fn discharge_change(previous: u8, amps: u8) -> Option<Event> {
+        match (previous, amps) {
+            (0, 0) => None,
+            (0, amps) => Some(Event::DischargePermitted(amps)),
+            (_, 0) => Some(Event::DischargeWithdrawn),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{trace, Chademo};
+
+    #[test]
+    fn event_test() {
+        let mut chademo = Chademo::new(16);
+        let mut events = Vec::new();
+        let log = include_str!("../CanLogs/nissan-leaf-chademo-start-stop.csv");
+        for record in trace::records(log) {
+            let record = record.unwrap();
+            let id = record.standard_id().unwrap();
+            let _ = chademo.decode_payload(id, record.data());
+            events.extend(chademo.events());
+        }
+        let key: Vec<_> = events
+            .iter()
+            .filter(|e| !matches!(e, Event::ChargeRequestChanged(_) | Event::SocChanged(_)))
+            .copied()
+            .collect();
+        assert_eq!(
+            key,
+            [
+                Event::VehicleReady,
+                Event::ContactorsClosed,
+                Event::DischargePermitted(2),
+                Event::StopRequested(StopReason::ChargingPermissionWithdrawn),
+                Event::DischargeWithdrawn,
+                Event::ContactorsOpened,
+            ]
+        );
+        // Leaf ramps 102.3 by 2 A per frame up to 14 A
+        let peak = events.iter().filter_map(|e| match e {
+            Event::ChargeRequestChanged(amps) => Some(*amps),
+            _ => None,
+        });
+        assert_eq!(peak.max(), Some(14));
+        assert_eq!(events.last(), Some(&Event::ContactorsOpened));
+    }
+
+    #[test]
+    fn fault_event_test() {
+        let mut chademo = Chademo::new(16);
+        let x102 = |faults: u8| [0x2, 0x9A, 0x1, 0x0E, faults, 0x1, 0x56, 0x0];
+        chademo.decode_payload(0x102, &x102(0)).unwrap();
+        chademo.decode_payload(0x102, &x102(0x01)).unwrap();
+        chademo.decode_payload(0x102, &x102(0x01)).unwrap();
+        chademo.decode_payload(0x102, &x102(0x03)).unwrap();
+        chademo.decode_payload(0x102, &x102(0)).unwrap();
+        assert!(chademo.events().eq([
+            Event::VehicleReady,
+            Event::ContactorsClosed,
+            Event::ChargeRequestChanged(14),
+            Event::SocChanged(86),
+            Event::FaultRaised(0x01),
+            Event::StopRequested(StopReason::BatteryFault(0x01)),
+            Event::FaultRaised(0x03),
+            Event::FaultCleared,
+        ]));
+
+        // A full queue drops the oldest
+        for soc in 0..crate::EVENT_QUEUE as u8 + 2 {
+            chademo
+                .decode_payload(0x102, &[0x2, 0x9A, 0x1, 0x0E, 0x0, 0x1, soc, 0x0])
+                .unwrap();
+        }
+        assert_eq!(chademo.events().next(), Some(Event::SocChanged(2)));
+        assert_eq!(chademo.events().count(), crate::EVENT_QUEUE - 1);
+    }
+}
@@ -0,0 +1,349 @@
+//! Charger side session sequencing on top of [`Chademo`].
+//!
+//! [`Chademo`] holds the protocol state, [`Evse`] walks it through lock, contactor close,
+//! charging and stop control from caller-supplied time and measured output.
+use crate::error::{ChademoError, StopReason};
+use crate::frames::{Payload, X101, X102};
+use crate::meter::{SessionMeter, SessionRecord};
+use crate::profile::ProfileRegistry;
+use crate::Chademo;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum EvseState {
+    /// Sending H'108/H'109, waiting for the vehicle to set 102.5.0
+    #[default]
+    Idle,
+    /// Connector locked (109.5.2), waiting for the vehicle contactors (102.5.3)
+    Preparation,
+    /// 109.5.0 set, output follows the charging current request (102.3)
+    Charging,
+    /// 109.5.5 set, output ramping down
+    Stopping,
+    /// Output off, connector unlocked
+    Finished,
+}
+
+/// Output requested from the power stage, negative current discharges the vehicle
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Setpoint {
+    pub enabled: bool,
+    pub voltage: f32,
+    pub current: f32,
+}
+
+/// DC converter behind the EVSE, implemented by the hardware driver or a simulation
+pub trait PowerStage {
+    /// Command the setpoint returned by [`Evse::poll`] at `now_ms`
+    fn apply(&mut self, now_ms: u64, setpoint: Setpoint);
+    /// Output voltage and current, current negative while discharging
+    fn measured(&mut self) -> (f32, f32);
+}
+
+#[derive(Debug, Clone)]
+pub struct Evse {
+    pub chademo: Chademo,
+    /// Matched against the vehicle once it permits charging and sent H'101, see
+    /// [`Chademo::identify`]
+    pub profiles: ProfileRegistry,
+    state: EvseState,
+    last_rx_ms: Option<u64>,
+    x101_received: bool,
+    last_tx_ms: Option<u64>,
+    user_stop: bool,
+    discharge_request: f32,
+    /// Energy of the running session, sampled while the output is live
+    pub meter: SessionMeter,
+    session: Option<SessionRecord>,
+}
+
+impl Evse {
+    /// CAN transmit period of H'108/H'109/H'208/H'209
+    pub const TX_PERIOD_MS: u64 = 100;
+    /// Valid H'102 missing for longer than this stops the session, unless
+    /// [`crate::profile::Quirks::rx_timeout_ms`] allows longer
+    pub const RX_TIMEOUT_MS: u64 = 1000;
+    /// 109.5.0 may only be cleared once output current is at or below this value
+    pub const STOP_CURRENT: f32 = 5.0;
+
+    pub fn new(chademo: Chademo) -> Self {
+        Self {
+            chademo,
+            profiles: ProfileRegistry::new(),
+            state: EvseState::Idle,
+            last_rx_ms: None,
+            x101_received: false,
+            last_tx_ms: None,
+            user_stop: false,
+            discharge_request: 0.0,
+            meter: SessionMeter::new(),
+            session: None,
+        }
+    }
+
+    pub fn state(&self) -> EvseState {
+        self.state
+    }
+
+    /// Decode a raw vehicle frame received at `now_ms`
+    pub fn receive(&mut self, now_ms: u64, id: u16, data: &[u8]) -> Result<(), ChademoError> {
+        self.chademo.decode_payload(id, data)?;
+        match id {
+            X101::ID => self.x101_received = true,
+            X102::ID => self.last_rx_ms = Some(now_ms),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// V2H discharge current wanted by the site, 0 to charge
+    ///
+    /// Capped by the vehicle limit (200.0) and the EVSE input current (208.3).
+    pub fn set_discharge_request(&mut self, amps: f32) {
+        self.discharge_request = amps.max(0.0);
+    }
+
+    /// Stop requested by the user or the station
+    pub fn stop(&mut self) {
+        self.user_stop = true;
+    }
+
+    fn rx_timeout(&self, now_ms: u64) -> bool {
+        let timeout = self.chademo.quirks().rx_timeout_ms;
+        let timeout = timeout.unwrap_or(Self::RX_TIMEOUT_MS);
+        self.last_rx_ms
+            .is_some_and(|rx| now_ms.saturating_sub(rx) > timeout)
+    }
+
+    /// `None` when [`Chademo`] already set stop control and recorded why
+    fn enter_stop(&mut self, reason: Option<StopReason>) -> EvseState {
+        if let Some(reason) = reason {
+            self.chademo.request_stop(reason);
+        }
+        EvseState::Stopping
+    }
+
+    /// Record of the last finished session
+    pub fn session(&self) -> Option<SessionRecord> {
+        self.session
+    }
+
+    /// Advance the sequence with the measured output and return the next power stage setpoint
+    pub fn poll(&mut self, now_ms: u64, measured_voltage: f32, measured_current: f32) -> Setpoint {
+        self.chademo.tick(now_ms);
+        let x102 = self.chademo.x102;
+        let stop = match self.user_stop {
+            true => Some(StopReason::User),
+            false => self.rx_timeout(now_ms).then_some(StopReason::Timeout),
+        };
+
+        use EvseState::*;
+        let previous = self.state;
+        self.state = match self.state {
+            Idle if stop.is_some() => self.enter_stop(stop),
+            Idle if self.last_rx_ms.is_some() && self.x101_received && x102.car_ready() => {
+                self.chademo.identify(&self.profiles);
+                if x102.target_battery_voltage > self.chademo.x108.avaible_output_voltage as f32 {
+                    self.chademo.x109.status.fault_battery_incompatibility = true;
+                    self.enter_stop(Some(StopReason::BatteryIncompatible))
+                } else {
+                    self.chademo.plug_lock(true);
+                    Preparation
+                }
+            }
+            Preparation if stop.is_some() || x102.stop() => {
+                self.enter_stop(stop.or(x102.stop_reason()))
+            }
+            Preparation if x102.contactors_closed() => {
+                self.chademo.charge_start();
+                Charging
+            }
+            Charging
+                if stop.is_some()
+                    || x102.stop()
+                    || self.chademo.x109_status().status_charger_stop_control =>
+            {
+                self.enter_stop(stop.or(x102.stop_reason()))
+            }
+            Stopping if measured_current.abs() <= Self::STOP_CURRENT => {
+                self.chademo.x109.status.status_station = false;
+                match !x102.contactors_closed() || self.rx_timeout(now_ms) {
+                    true => {
+                        self.chademo.plug_lock(false);
+                        self.chademo.charge_stop();
+                        self.session = self.meter.finish(now_ms, self.chademo.stop_reason());
+                        Finished
+                    }
+                    false => Stopping,
+                }
+            }
+            state => state,
+        };
+        if self.state != previous {
+            info!("evse {} -> {}", previous, self.state);
+        }
+
+        let setpoint = match self.state {
+            Charging => Setpoint {
+                enabled: true,
+                voltage: x102.target_battery_voltage,
+                current: match self.discharge_amps() {
+                    0.0 => self
+                        .chademo
+                        .requested_charging_amps()
+                        .min(self.chademo.x108.available_output_current as f32)
+                        .min(self.chademo.charge_current_limit() as f32),
+                    amps => -amps,
+                },
+            },
+            Stopping => Setpoint {
+                enabled: true,
+                voltage: x102.target_battery_voltage,
+                current: 0.0,
+            },
+            _ => Setpoint::default(),
+        };
+        // 109.1-3 report the present output, 208.0 the discharge current
+        if self.state != Finished {
+            self.chademo.x109.output_voltage = measured_voltage;
+            self.chademo.x109.output_current = measured_current.max(0.0) as u8;
+            self.chademo
+                .x208
+                .set_discharge_current((-measured_current).max(0.0) as u8);
+        }
+        if matches!(self.state, Charging | Stopping) {
+            let soc = self.chademo.x102.state_of_charge;
+            self.meter
+                .sample(now_ms, measured_voltage, measured_current, soc);
+        }
+        setpoint
+    }
+
+    fn discharge_amps(&self) -> f32 {
+        self.discharge_request
+            .min(self.chademo.requested_discharging_amps())
+            .min(self.chademo.x208.get_input_current() as f32)
+    }
+
+    /// True once per transmit period, send [`Chademo::tx_payloads`] when set
+    ///
+    /// The frames are recorded in [`Chademo::history`] as sent.
+    pub fn tx_due(&mut self, now_ms: u64) -> bool {
+        match self.last_tx_ms {
+            Some(tx) if now_ms.saturating_sub(tx) < Self::TX_PERIOD_MS => false,
+            _ => {
+                self.last_tx_ms = Some(now_ms);
+                self.chademo.record_tx(now_ms);
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::compliance::ComplianceChecker;
+    use crate::emulator::{VehicleEmulator, VehicleParams, VehicleState};
+
+    /// EVSE and emulated vehicle exchanging payloads with an ideal power stage
+    fn run(evse: &mut Evse, ev: &mut VehicleEmulator, from_ms: u64, until_ms: u64) {
+        let mut checker = ComplianceChecker::new();
+        let mut output = (0.0, 0.0);
+        for now in (from_ms..=until_ms).step_by(10) {
+            ev.tick(now);
+            if now % Evse::TX_PERIOD_MS == 0 {
+                for (id, data) in ev.tx_payloads() {
+                    checker.check(now, id, &data, |v| panic!("{v}")).unwrap();
+                    evse.receive(now, id, &data).unwrap();
+                }
+            }
+            let setpoint = evse.poll(now, output.0, output.1);
+            output = match setpoint.enabled {
+                true => (setpoint.voltage, setpoint.current),
+                false => (0.0, 0.0),
+            };
+            if evse.tx_due(now) {
+                for (id, data) in evse.chademo.tx_payloads() {
+                    checker.check(now, id, &data, |v| panic!("{v}")).unwrap();
+                    ev.receive(id, &data).unwrap();
+                }
+            }
+            if evse.state() == EvseState::Finished {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn charge_session_test() {
+        let params = VehicleParams {
+            target_soc: 74,
+            ..Default::default()
+        };
+        let mut ev = VehicleEmulator::new(params, 73.0);
+        let mut evse = Evse::new(Chademo::new(16));
+        run(&mut evse, &mut ev, 0, 10 * 60 * 1000);
+        assert_eq!(evse.state(), EvseState::Finished);
+        assert_eq!(ev.state(), VehicleState::Finished);
+        assert!(ev.soc() >= 74.0);
+        assert!(!evse.chademo.x109_status().status_station);
+        assert!(!evse.chademo.x109_status().status_vehicle_connector_lock);
+
+        let session = evse.session().unwrap();
+        assert_eq!(
+            session.stop_reason,
+            Some(StopReason::ChargingPermissionWithdrawn)
+        );
+        assert_eq!((session.start_soc, session.end_soc), (73, 74));
+        assert_eq!(session.peak_charge_w, 410.0 * 14.0);
+        assert_eq!(session.discharged_wh, 0.0);
+        // At least the 1 % of the 22.2 kWh pack the vehicle absorbed
+        assert!((222.0..240.0).contains(&session.charged_wh), "{session}");
+    }
+
+    #[test]
+    fn user_stop_test() {
+        let mut ev = VehicleEmulator::new(VehicleParams::default(), 50.0);
+        let mut evse = Evse::new(Chademo::new(16));
+        run(&mut evse, &mut ev, 0, 2_000);
+        assert_eq!(evse.state(), EvseState::Charging);
+        evse.stop();
+        run(&mut evse, &mut ev, 2_010, 4_000);
+        assert_eq!(evse.state(), EvseState::Finished);
+        assert_eq!(ev.state(), VehicleState::Finished);
+        assert_eq!(evse.chademo.stop_reason(), Some(StopReason::User));
+        assert_eq!(evse.session().unwrap().stop_reason, Some(StopReason::User));
+    }
+
+    #[test]
+    fn identify_after_x101_test() {
+        let mut ev = VehicleEmulator::new(VehicleParams::default(), 50.0);
+        let mut evse = Evse::new(Chademo::new(16));
+        for now in (0..=1_000).step_by(100) {
+            ev.tick(now);
+            for (id, data) in ev
+                .tx_payloads()
+                .into_iter()
+                .filter(|(id, _)| *id != X101::ID)
+            {
+                evse.receive(now, id, &data).unwrap();
+            }
+            evse.poll(now, 0.0, 0.0);
+            for (id, data) in evse.chademo.tx_payloads() {
+                ev.receive(id, &data).unwrap();
+            }
+        }
+        assert!(evse.chademo.x102.car_ready());
+        assert_eq!(evse.state(), EvseState::Idle);
+        assert_eq!(evse.chademo.profile(), None);
+
+        for (id, data) in ev.tx_payloads() {
+            evse.receive(1_100, id, &data).unwrap();
+        }
+        evse.poll(1_100, 0.0, 0.0);
+        assert_eq!(evse.state(), EvseState::Preparation);
+        assert_eq!(evse.chademo.profile(), Some("Nissan Leaf ZE0"));
+    }
+}
@@ -0,0 +1,224 @@
+//! Scripted vehicle misbehaviour for [`crate::sim`] sessions.
+//!
+//! [`FaultInjector`] wraps a node and alters the frames it sends while each scripted [`Fault`]
+//! is active, so every stop condition of the charging sequence can be driven from a test.
+use std::vec::Vec;
+
+use crate::frames::X102Faults;
+use crate::proxy::{Decoded, Rewrite};
+use crate::sim::{Node, SimFrame};
+
+#[derive(Debug, Clone, Copy)]
+pub enum Fault {
+    /// 102.5.0 cleared, charging/discharging no longer permitted
+    ClearChargingPermission,
+    /// 102.4 fault bits
+    BatteryFaults(X102Faults),
+    /// 102.5.1 set, shift lever out of park
+    ShiftOutOfPark,
+    /// 102.5.2 set, vehicle charging system malfunction
+    ChargingSystemFault,
+    /// 102.3 replaced
+    CurrentRequest(u8),
+    /// Nothing is sent
+    Silence,
+    /// Frames with this ID are sent with `len` data bytes
+    Dlc { id: u16, len: usize },
+    /// Any change to the decoded frames
+    Custom(fn(&mut Decoded)),
+}
+
+impl Rewrite for Fault {
+    fn rewrite(&mut self, frame: &mut Decoded) {
+        use Fault::*;
+        match (*self, frame) {
+            (ClearChargingPermission, Decoded::X102(x102)) => {
+                x102.status.status_vehicle_charging = false
+            }
+            (BatteryFaults(faults), Decoded::X102(x102)) => x102.set_faults(faults),
+            (ShiftOutOfPark, Decoded::X102(x102)) => {
+                x102.status.status_vehicle_shifter_position = true
+            }
+            (ChargingSystemFault, Decoded::X102(x102)) => x102.status.status_charging_system = true,
+            (CurrentRequest(amps), Decoded::X102(x102)) => x102.charging_current_request = amps,
+            (Custom(rewrite), frame) => rewrite(frame),
+            _ => (),
+        }
+    }
+}
+
+/// One scripted fault, active from `from_ms` until `until_ms`
+#[derive(Debug, Clone, Copy)]
+pub struct Scripted {
+    pub from_ms: u64,
+    pub until_ms: Option<u64>,
+    pub fault: Fault,
+}
+
+impl Scripted {
+    fn active(&self, now_ms: u64) -> bool {
+        now_ms >= self.from_ms && self.until_ms.is_none_or(|until| now_ms < until)
+    }
+}
+
+pub struct FaultInjector<N> {
+    pub inner: N,
+    pub script: Vec<Scripted>,
+}
+
+impl<N: Node> FaultInjector<N> {
+    pub fn new(inner: N) -> Self {
+        Self {
+            inner,
+            script: Vec::new(),
+        }
+    }
+
+    /// Apply `fault` from `from_ms` for the rest of the session
+    pub fn at(mut self, from_ms: u64, fault: Fault) -> Self {
+        self.script.push(Scripted {
+            from_ms,
+            until_ms: None,
+            fault,
+        });
+        self
+    }
+
+    /// Apply `fault` from `from_ms` until `until_ms`
+    pub fn between(mut self, from_ms: u64, until_ms: u64, fault: Fault) -> Self {
+        self.script.push(Scripted {
+            from_ms,
+            until_ms: Some(until_ms),
+            fault,
+        });
+        self
+    }
+
+    fn alter(&self, now_ms: u64, mut frame: SimFrame) -> Option<SimFrame> {
+        for scripted in self.script.iter().filter(|s| s.active(now_ms)) {
+            let mut fault = scripted.fault;
+            match fault {
+                Fault::Silence => return None,
+                Fault::Dlc { id, len } if id == frame.id => frame.data.resize(len, 0),
+                _ => {
+                    if let Ok(mut decoded) = Decoded::from_payload(frame.id, &frame.data) {
+                        fault.rewrite(&mut decoded);
+                        frame.data = decoded.to_payload().to_vec();
+                    }
+                }
+            }
+        }
+        Some(frame)
+    }
+}
+
+impl<N: Node> Node for FaultInjector<N> {
+    fn receive(&mut self, now_ms: u64, frame: &SimFrame) {
+        self.inner.receive(now_ms, frame);
+    }
+
+    fn tick(&mut self, now_ms: u64, tx: &mut Vec<SimFrame>) {
+        let mut sent = Vec::new();
+        self.inner.tick(now_ms, &mut sent);
+        tx.extend(
+            sent.into_iter()
+                .filter_map(|frame| self.alter(now_ms, frame)),
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::emulator::{VehicleEmulator, VehicleParams};
+    use crate::error::StopReason;
+    use crate::evse::{Evse, EvseState};
+    use crate::sim::{EvseNode, NodeId, SimBus, VehicleNode};
+    use crate::Chademo;
+
+    /// Charging by 3 s, fault injected at 5 s
+    const FAULT_MS: u64 = 5_000;
+
+    fn session(fault: Fault) -> (SimBus, NodeId) {
+        let mut bus = SimBus::new(10);
+        let evse = bus.add(EvseNode::new(Evse::new(Chademo::new(16))));
+        let ev = VehicleNode::new(VehicleEmulator::new(VehicleParams::default(), 50.0));
+        bus.add(FaultInjector::new(ev).at(FAULT_MS, fault));
+        assert!(bus.run_until(3_000, |bus| {
+            bus.node::<EvseNode>(evse).state() == EvseState::Charging
+        }));
+        bus.run_for(FAULT_MS - bus.now());
+        (bus, evse)
+    }
+
+    /// Stop control (109.5.5) for `reason` within `within_ms` of the fault, output down and
+    /// 109.5.0 cleared
+    fn assert_stops(fault: Fault, reason: StopReason, within_ms: u64) -> SimBus {
+        let (mut bus, evse) = session(fault);
+        assert_eq!(bus.node::<EvseNode>(evse).state(), EvseState::Charging);
+        assert!(
+            bus.run_until(within_ms, |bus| {
+                bus.node::<EvseNode>(evse).state() >= EvseState::Stopping
+            }),
+            "{fault:?} did not stop"
+        );
+        let chademo = &bus.node::<EvseNode>(evse).evse.chademo;
+        assert!(
+            chademo.x109_status().status_charger_stop_control,
+            "{fault:?}"
+        );
+        assert_eq!(chademo.stop_reason(), Some(reason));
+        bus.run_for(100);
+        let node = bus.node::<EvseNode>(evse);
+        assert_eq!(node.output.1, 0.0, "{fault:?}");
+        assert!(!node.evse.chademo.x109_status().status_station, "{fault:?}");
+        bus
+    }
+
+    #[test]
+    fn vehicle_stop_test() {
+        // Decoded on the next H'102, at most one vehicle period later
+        use StopReason::*;
+        assert_stops(
+            Fault::ClearChargingPermission,
+            ChargingPermissionWithdrawn,
+            110,
+        );
+        assert_stops(Fault::ShiftOutOfPark, ShifterPosition, 110);
+        assert_stops(Fault::ChargingSystemFault, VehicleChargingSystemFault, 110);
+        let faults = X102Faults {
+            fault_battery_overvoltage: true,
+            ..Default::default()
+        };
+        assert_stops(Fault::BatteryFaults(faults), BatteryFault(0x10), 110);
+        let faults = X102Faults {
+            fault_high_battery_temperature: true,
+            ..Default::default()
+        };
+        assert_stops(Fault::BatteryFaults(faults), BatteryFault(0x02), 110);
+    }
+
+    #[test]
+    fn communication_loss_test() {
+        let within = Evse::RX_TIMEOUT_MS + 200;
+        let bus = assert_stops(Fault::Silence, StopReason::Timeout, within);
+        assert_eq!(bus.node::<EvseNode>(NodeId(0)).rx_errors, 0);
+
+        let dlc = Fault::Dlc { id: 0x102, len: 7 };
+        let bus = assert_stops(dlc, StopReason::Timeout, within);
+        assert!(bus.node::<EvseNode>(NodeId(0)).rx_errors > 0);
+    }
+
+    #[test]
+    fn transient_fault_test() {
+        // Faults outside the scripted window leave the session alone
+        let mut bus = SimBus::new(10);
+        let evse = bus.add(EvseNode::new(Evse::new(Chademo::new(16))));
+        let ev = VehicleNode::new(VehicleEmulator::new(VehicleParams::default(), 50.0));
+        bus.add(FaultInjector::new(ev).between(0, 50, Fault::CurrentRequest(100)));
+        bus.run_for(3_000);
+        let node = bus.node::<EvseNode>(evse);
+        assert_eq!(node.state(), EvseState::Charging);
+        assert_eq!(node.output.1, 14.0);
+    }
+}
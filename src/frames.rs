@@ -1,14 +1,21 @@
-#[cfg(feature = "eh1")]
-pub(crate) use embedded_can::Frame;
-#[cfg(feature = "eh0")]
-pub(crate) use embedded_hal::can::Frame;
+use crate::error::{ChademoError, StopReason};
 
-use crate::interface;
-use interface::raw_to_id;
-use std::marker::PhantomData;
+/// Raw codec shared by every CHAdeMO frame, independent of any CAN driver.
+///
+/// CAN driver adapters build on this, see `FrameCodec` in the `eh0` and `eh1` modules.
+pub trait Payload: Sized {
+    /// Standard 11 bit CAN identifier of the frame
+    const ID: u16;
+    /// Encode into the 8 byte data field
+    fn to_payload(&self) -> [u8; 8];
+    /// Decode from the data field, which must be exactly 8 bytes
+    fn from_payload(data: &[u8]) -> Result<Self, ChademoError>;
+}
 
 #[doc = r"! Vehicle CAN frames"]
 #[derive(Debug, Default, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct X100 {
     /// Set “minimum current” defined by vehicle
     pub minimum_charge_current: u8,
@@ -18,26 +25,38 @@ pub struct X100 {
     pub maximum_battery_voltage: f32,
     /// Set fixed value (0x64: 100 %) related to charged rate
     pub constant_of_charging_rate_indication: u8,
+    /// Reserved bytes 100.1 and 100.7, kept for re-encoding
+    reserved: [u8; 2],
 }
 
-impl<T> From<&T> for X100
-where
-    T: for<'a> Frame,
-{
-    fn from(frame: &T) -> Self {
-        let data = data_sanity(frame, 0x100, 8);
-        X100 {
+impl Payload for X100 {
+    const ID: u16 = 0x100;
+    fn to_payload(&self) -> [u8; 8] {
+        let mut data = [0u8; 8];
+        data[0] = self.minimum_charge_current;
+        data[1] = self.reserved[0];
+        [data[2], data[3]] = (self.minimum_battery_voltage as u16).to_le_bytes();
+        [data[4], data[5]] = (self.maximum_battery_voltage as u16).to_le_bytes();
+        data[6] = self.constant_of_charging_rate_indication;
+        data[7] = self.reserved[1];
+        data
+    }
+    fn from_payload(data: &[u8]) -> Result<Self, ChademoError> {
+        let data = data_sanity(data)?;
+        Ok(X100 {
             minimum_battery_voltage: u16::from_le_bytes(data[2..=3].try_into().unwrap()) as f32,
             maximum_battery_voltage: u16::from_le_bytes(data[4..=5].try_into().unwrap()) as f32,
             constant_of_charging_rate_indication: data[6],
             minimum_charge_current: data[0],
-        }
+            reserved: [data[1], data[7]],
+        })
     }
 }
 
 /// Vehicle CAN frame
-#[allow(dead_code)]
 #[derive(Debug, Default, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct X101 {
     /// Maximum charging time that vehicle permits charger
     max_charging_time_10s_bit: u8,
@@ -47,25 +66,136 @@ pub struct X101 {
     estimated_charging_time: u8,
     /// Set total capacity of battery
     rated_battery_capacity: f32,
+    /// Reserved bytes 101.0, 101.4 and 101.7, kept for re-encoding
+    reserved: [u8; 3],
 }
 
-impl<T> From<&T> for X101
-where
-    T: for<'a> Frame,
-{
-    fn from(frame: &T) -> Self {
-        let data = data_sanity(frame, 0x101, 8);
-        X101 {
+impl Payload for X101 {
+    const ID: u16 = 0x101;
+    fn to_payload(&self) -> [u8; 8] {
+        let mut data = [0u8; 8];
+        data[0] = self.reserved[0];
+        data[1] = self.max_charging_time_10s_bit;
+        data[2] = self.max_charging_time_1min_bit;
+        data[3] = self.estimated_charging_time;
+        data[4] = self.reserved[1];
+        [data[5], data[6]] = ((self.rated_battery_capacity * 10.0 + 0.5) as u16).to_le_bytes();
+        data[7] = self.reserved[2];
+        data
+    }
+    fn from_payload(data: &[u8]) -> Result<Self, ChademoError> {
+        let data = data_sanity(data)?;
+        Ok(X101 {
             max_charging_time_10s_bit: data[1],
             max_charging_time_1min_bit: data[2],
             estimated_charging_time: data[3],
-            rated_battery_capacity: u16::from_le_bytes(data[5..=6].try_into().unwrap()) as f32,
+            rated_battery_capacity: u16::from_le_bytes(data[5..=6].try_into().unwrap()) as f32
+                * 0.1,
+            reserved: [data[0], data[4], data[7]],
+        })
+    }
+}
+impl X101 {
+    /// Vehicle limits in seconds and kWh, encoded with the rules of the accessors below
+    pub fn new(
+        max_charging_time: u32,
+        estimated_charging_time: u32,
+        rated_battery_capacity: f32,
+    ) -> Self {
+        let (max_charging_time_10s_bit, max_charging_time_1min_bit) = match max_charging_time {
+            t if t <= 0xFE * 10 => (t.div_ceil(10) as u8, 0),
+            t => (0xFF, t.div_ceil(60).min(0xFF) as u8),
+        };
+        Self {
+            max_charging_time_10s_bit,
+            max_charging_time_1min_bit,
+            estimated_charging_time: estimated_charging_time.div_ceil(60).min(0xFF) as u8,
+            rated_battery_capacity,
+            reserved: [0; 3],
+        }
+    }
+    /// 101.1-2 - Maximum charging time in seconds that the vehicle permits the charger.
+    ///
+    /// 101.1 counts in 10 s units, when it is 0xFF 101.2 counts in 1 min units instead.
+    /// `None` while the vehicle has not set a limit.
+    pub fn max_charging_time(&self) -> Option<u32> {
+        let seconds = match self.max_charging_time_10s_bit {
+            0xFF => self.max_charging_time_1min_bit as u32 * 60,
+            t => t as u32 * 10,
+        };
+        (seconds > 0).then_some(seconds)
+    }
+    /// 101.3 - Estimated time in seconds until the vehicle stops charging (1 min units)
+    pub fn estimated_charging_time(&self) -> u32 {
+        self.estimated_charging_time as u32 * 60
+    }
+    /// 101.5-6 - Rated capacity of the traction battery in kWh (0.1 kWh units)
+    pub fn rated_battery_capacity(&self) -> f32 {
+        self.rated_battery_capacity
+    }
+}
+
+/// CHAdeMO control protocol number, 102.0 (vehicle) and 109.0 (EVSE)
+///
+/// Does not gate H'208/H'209: those are V2H guideline frames, not tied to the control
+/// protocol number, and the charger in `CanLogs/nissan-leaf-chademo-start-stop.csv` sends them
+/// throughout. [`crate::Chademo::tx_payloads`] always includes them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ProtocolNumber {
+    /// 0x01 - CHAdeMO 0.9 / 0.9.1
+    V0_9,
+    /// 0x02 - CHAdeMO 1.0.0 / 1.0.1
+    V1_0,
+    /// 0x03 - CHAdeMO 1.1 and later
+    V1_1,
+}
+impl ProtocolNumber {
+    /// Highest protocol supported by both sides
+    ///
+    /// Vehicles reporting a number this crate does not know are capped to `evse`,
+    /// 0x00 (pre 0.9) is incompatible.
+    pub fn negotiate(ev: u8, evse: ProtocolNumber) -> Result<Self, ChademoError> {
+        match ev {
+            0 => Err(ChademoError::IncompatibleProtocol(ev)),
+            ev => Ok(ProtocolNumber::try_from(ev).unwrap_or(evse).min(evse)),
+        }
+    }
+    /// 1.0 onwards reports remaining charging time in minutes (109.7) and sets 109.6 to 0xFF
+    pub fn remaining_time_in_minutes(self) -> bool {
+        self >= ProtocolNumber::V1_0
+    }
+    /// 1.1 onwards allows the EVSE to update available output current (108.3) while charging
+    pub fn dynamic_current_control(self) -> bool {
+        self >= ProtocolNumber::V1_1
+    }
+}
+impl TryFrom<u8> for ProtocolNumber {
+    type Error = ChademoError;
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x01 => Ok(ProtocolNumber::V0_9),
+            0x02 => Ok(ProtocolNumber::V1_0),
+            0x03 => Ok(ProtocolNumber::V1_1),
+            n => Err(ChademoError::IncompatibleProtocol(n)),
+        }
+    }
+}
+impl From<ProtocolNumber> for u8 {
+    fn from(val: ProtocolNumber) -> Self {
+        match val {
+            ProtocolNumber::V0_9 => 0x01,
+            ProtocolNumber::V1_0 => 0x02,
+            ProtocolNumber::V1_1 => 0x03,
         }
     }
 }
 
 /// Vehicle CAN frame
 #[derive(Debug, Default, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct X102 {
     /// CHAdeMO protocol number
     pub control_protocol_number_ev: u8,
@@ -77,6 +207,41 @@ pub struct X102 {
     pub status: X102Status,
     /// state of charge of battery
     pub state_of_charge: u8,
+    /// Undefined bits of 102.4 and 102.5 plus reserved byte 102.7, kept for re-encoding
+    reserved: [u8; 3],
+}
+
+impl Payload for X102 {
+    const ID: u16 = 0x102;
+    fn to_payload(&self) -> [u8; 8] {
+        let mut data = [0u8; 8];
+        data[0] = self.control_protocol_number_ev;
+        [data[1], data[2]] = (self.target_battery_voltage as u16).to_le_bytes();
+        data[3] = self.charging_current_request;
+        data[4] = u8::from(self.faults) | self.reserved[0];
+        data[5] = u8::from(self.status) | self.reserved[1];
+        data[6] = self.state_of_charge;
+        data[7] = self.reserved[2];
+        data
+    }
+    fn from_payload(data: &[u8]) -> Result<Self, ChademoError> {
+        let data = data_sanity(data)?;
+        let faults = X102Faults::from(data[4]);
+        let status = X102Status::from(data[5]);
+        Ok(X102 {
+            control_protocol_number_ev: data[0],
+            target_battery_voltage: u16::from_le_bytes(data[1..=2].try_into().unwrap()) as f32,
+            charging_current_request: data[3],
+            faults,
+            status,
+            state_of_charge: data[6],
+            reserved: [
+                data[4] & !u8::from(faults),
+                data[5] & !u8::from(status),
+                data[7],
+            ],
+        })
+    }
 }
 impl X102 {
     pub fn fault(&self) -> bool {
@@ -100,30 +265,46 @@ impl X102 {
             && self.status.status_vehicle_charging
             && self.target_battery_voltage > 0.0
     }
+    /// Vehicle request to stop charging/discharging
+    ///
+    /// Any 102.4 fault, 102.5.4, 102.5.2 or 102.5.1 set, or 102.5.0 cleared.
     pub fn stop(&self) -> bool {
-        false
+        self.fault()
+            | self.status.status_normal_stop_request
+            | self.status.status_charging_system
+            | self.status.status_vehicle_shifter_position
+            | !self.status.status_vehicle_charging
+    }
+    /// Cause behind [`Self::stop`], battery faults first
+    pub fn stop_reason(&self) -> Option<StopReason> {
+        let status = self.status;
+        let reason = if self.fault() {
+            StopReason::BatteryFault(self.faults.into())
+        } else if status.status_charging_system {
+            StopReason::VehicleChargingSystemFault
+        } else if status.status_vehicle_shifter_position {
+            StopReason::ShifterPosition
+        } else if status.status_normal_stop_request {
+            StopReason::NormalStopRequest
+        } else if !status.status_vehicle_charging {
+            StopReason::ChargingPermissionWithdrawn
+        } else {
+            return None;
+        };
+        Some(reason)
     }
-}
-
-impl<T> From<&T> for X102
-where
-    T: Frame,
-{
-    fn from(frame: &T) -> X102 {
-        let data = data_sanity(frame, 0x102, 8);
-        X102 {
-            control_protocol_number_ev: data[0],
-            target_battery_voltage: u16::from_le_bytes(data[1..=2].try_into().unwrap()) as f32,
-            charging_current_request: data[3],
-            faults: From::from(data[4]),
-            status: From::from(data[5]),
-            state_of_charge: data[6],
-        }
+    pub fn faults(&self) -> X102Faults {
+        self.faults
+    }
+    pub fn set_faults(&mut self, faults: X102Faults) {
+        self.faults = faults;
     }
 }
 
 /// 1 = error, 0 = normal
 #[derive(Debug, Default, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct X102Faults {
     /// 102.4.4
     /// - Battery voltage deviation error
@@ -133,6 +314,7 @@ pub struct X102Faults {
     pub fault_high_battery_temperature: bool,
     /// 102.4.2
     /// - Battery current deviation error
+    ///
     /// — If the EVSE’s output exceeds the maximum charge current continually, the flag shall be changed to 1. The overcurrent threshold shall be set at 10 A (absolute value) or more, and the time threshold shall be set at 5sec or more
     /// — If the EVSE’s input exceeds the range of the maximum discharge current continually, the flag shall be changed to 1. The overcurrent threshold shall be set at 10 A (absolute value) or more and the time threshold shall be set at 5sec or more
     /// — The vehicle charge/discharge enabled and switch (k) shall be turned off at the same time
@@ -143,6 +325,7 @@ pub struct X102Faults {
     pub fault_battery_undervoltage: bool,
     /// 102.4.0
     /// - Status flag indicating the voltage status of on-board battery
+    ///
     /// Regardless of opto-coupler (j) status, the EVSE shall regard this flag as charging termination order from the vehicle if it is equal to 1, and stop charging.
     pub fault_battery_overvoltage: bool,
 }
@@ -156,6 +339,20 @@ impl From<X102Faults> for bool {
     }
 }
 
+impl From<X102Faults> for u8 {
+    fn from(val: X102Faults) -> Self {
+        let mut result: u8 = 0;
+
+        result |= (val.fault_battery_overvoltage as u8) << 4;
+        result |= (val.fault_battery_undervoltage as u8) << 3;
+        result |= (val.fault_battery_current_deviation as u8) << 2;
+        result |= (val.fault_high_battery_temperature as u8) << 1;
+        result |= val.fault_battery_voltage_deviation as u8;
+
+        result
+    }
+}
+
 impl From<u8> for X102Faults {
     fn from(value: u8) -> Self {
         Self {
@@ -169,35 +366,43 @@ impl From<u8> for X102Faults {
 }
 
 #[derive(Debug, Default, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct X102Status {
     /// 102.5.7
     /// - The flag indicating the vehicle is compatible with discharging
+    ///
     /// The value shall be set from the first time of the CAN communication, and it shall not be updated. However, if it is inevitable to reset the value, e.g. for battery protection, the value is updated from 1 to 0 and only discharging shall be prohibited. — The value indicates the compatibility with the V2H charge/discharge mode (compatible: 1, incompatible: 0)
     pub status_discharge_compatible: bool,
     /// 102.5.4
     /// - Flag used by the vehicle to instruct the EVSE to stop charging control. -
+    ///
     /// This value shall be updated until initial value of “Charging current request” is set. Do not update this value after initial value transmission.
     pub status_normal_stop_request: bool,
     /// 102.5.3
     ///  - Flag indicating the OPEN/CLOSE status of EV contactors and the result of vehicle contactor welding detection.
+    ///
     /// Set the flag to 0 when the vehicle relay is closed, and set as 1 after the termination of welding detection. - Set the flag to 0 when the vehicle relay is closed, and set as 1 after the termination of welding detection.
     pub status_vehicle: bool, // true EV contactors open
     /// 102.5.2
     /// - Flag indicating the presence of the malfunction originated in the vehicle among the malfunctions detected by the vehicle.
+    ///
     /// Update as needed, and hold “1” after the malfunction is determined. — Regardless of the condition of the opto-coupler (j), if this flag is 0, it shall be considered as the vehicle's request to stop charging/discharging, and the EVSE shall move to the stop control.
     pub status_charging_system: bool, // false = ok / true = fault
     /// 102.5.1
     /// - Status flag indicating the shift lever position
+    ///
     /// — Set this flag to 0 when the shift lever is in “parking” position. Set to 1 when it is in other position. — Turn the switch (k) OFF if the shift position is changed except “parking” during charging.
     pub status_vehicle_shifter_position: bool, // false = ok
     /// 102.5.0
     /// - Flag indicating charging/dischar ging permission status of the vehicle.
+    ///
     /// Charging/discharging enabled: 1, charging/discharging disabled: 0
     /// — After CAN communication starts and the vehicle sends the EVSE data required for prior to a start of charging/discharging, change the flag 0 to 1. — Change this flag 1 to 0 when the vehicle sends the “charging/discharging stop” notification to the EVSE. Regardless of the condition of the opto-coupler (j), if this flag is 0, it shall be considered as the vehicle's request to stop charging/discharging, and the EVSE shall move to the stop control.— When this flag is 0, the insulation test shall not be conducted.
     pub status_vehicle_charging: bool,
 }
-impl std::fmt::Display for X102Status {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+impl core::fmt::Display for X102Status {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
         write!(
             f,
             "102.5.0:{} 1:{} 2:{} 3:{} 4:{} 7:{}",
@@ -238,11 +443,10 @@ impl From<X102Status> for u8 {
 }
 
 /// EVSE CAN frame
-#[derive(Debug, Copy, Clone)]
-pub struct X108<T>
-where
-    T: Frame,
-{
+#[derive(Debug, Default, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct X108 {
     /// 108.3 - Current that the EVSE can output at present.
     ///
     /// This value shall be set from the initial CAN communication. The initial value shall be the maximum current that can be output by the EVSE, and during the charging/discharging, the value shall be updated from time to time as the current which can be output by the EVSE.
@@ -252,6 +456,7 @@ where
     ///
     /// Set the number from initial CAN data transmission and do not update it.
     /// If the EVSE receives “target battery voltage” exceeding this value from the vehicle, regard this situation as “Battery incompatible” and shift to charge termination process.
+    #[cfg_attr(feature = "serde", serde(rename = "available_output_voltage"))]
     pub avaible_output_voltage: u16,
     /// 108.0 - Identifier indicating characteristic of output circuit of EVSE which corresponds to welding detection of EV contactor.
     pub welding_detection: u8,
@@ -260,30 +465,39 @@ where
     /// This flag may be updated until the initial value of charging current request is sent from the vehicle.
     /// — The EVSE shall compare vehicle CAN “maximum battery voltage” with charger CAN “available output voltage,” set the lower value to this value. — When circuit voltage reaches to this value, the EVSE stops charging output.
     pub threshold_voltage: u16,
-    phantom: PhantomData<T>,
+    /// Reserved bytes 108.6 and 108.7, kept for re-encoding
+    reserved: [u8; 2],
 }
 
-impl<T> X108<T>
-where
-    T: Frame,
-{
-    pub fn to_can(&self) -> Option<T> {
+impl Payload for X108 {
+    const ID: u16 = 0x108;
+    fn to_payload(&self) -> [u8; 8] {
         let aov = self.avaible_output_voltage.to_le_bytes();
         let tv = self.threshold_voltage.to_le_bytes();
-        T::new(
-            raw_to_id(0x108),
-            &[
-                self.welding_detection,
-                aov[0],
-                aov[1],
-                self.available_output_current,
-                tv[0],
-                tv[1],
-                0,
-                0,
-            ],
-        )
-    }
+        [
+            self.welding_detection,
+            aov[0],
+            aov[1],
+            self.available_output_current,
+            tv[0],
+            tv[1],
+            self.reserved[0],
+            self.reserved[1],
+        ]
+    }
+    fn from_payload(data: &[u8]) -> Result<Self, ChademoError> {
+        let data = data_sanity(data)?;
+        Ok(Self {
+            welding_detection: data[0],
+            avaible_output_voltage: u16::from_le_bytes([data[1], data[2]]),
+            available_output_current: data[3],
+            threshold_voltage: u16::from_le_bytes([data[4], data[5]]),
+            reserved: [data[6], data[7]],
+        })
+    }
+}
+
+impl X108 {
     pub fn new(
         available_output_current: u8,
         avaible_output_voltage: u16,
@@ -295,12 +509,14 @@ where
             avaible_output_voltage,
             welding_detection: welding_detection.into(),
             threshold_voltage,
-            phantom: PhantomData,
+            reserved: [0; 2],
         }
     }
 }
 
 #[derive(Default, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct X109Status {
     /// 109.5.5 - Set this flag to 1 before charging (e.g., initial value and during insulation test).
     ///
@@ -327,8 +543,17 @@ pub struct X109Status {
     /// Set 0 to this flag before charging (e.g., initial value, during insulation test) and at the end of the charging (shifting to stop process and charging current decreases less than or equal to 5 A). Set 1 to this flag during charging
     pub status_station: bool,
 }
-impl std::fmt::Display for X109Status {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+
+impl X109Status {
+    /// Any of 109.5.4, 109.5.3 or 109.5.1 set
+    pub fn fault(&self) -> bool {
+        self.fault_charging_system_malfunction
+            | self.fault_battery_incompatibility
+            | self.fault_station_malfunction
+    }
+}
+impl core::fmt::Display for X109Status {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
         write!(
             f,
             "109.5.0:{} 1:{} 2:{} 3:{} 4:{} 5:{}",
@@ -367,32 +592,53 @@ impl From<u8> for X109Status {
 }
 /// EVSE CAN frame
 #[derive(Default, Debug, Clone, Copy)]
-pub struct X109<T> {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct X109 {
     pub status: X109Status,
     control_protocol_number_qc: u8,
     pub output_voltage: f32,
     pub output_current: u8,
+    #[cfg_attr(feature = "serde", serde(rename = "discharge_compatibility"))]
     discharge_compatitiblity: bool,
     pub remaining_charging_time_10s_bit: u8,
     pub remaining_charging_time_1min_bit: u8,
-    phantom: PhantomData<T>,
+    /// Undefined bits of 109.4 and 109.5, kept for re-encoding
+    reserved: [u8; 2],
 }
 
-impl<T: Frame> X109<T> {
-    pub fn to_can(&self) -> Option<T> {
+impl Payload for X109 {
+    const ID: u16 = 0x109;
+    fn to_payload(&self) -> [u8; 8] {
         let mut result = [0u8; 8];
 
         result[0] = self.control_protocol_number_qc;
         let voltage_bytes: [u8; 2] = ((self.output_voltage) as u16).to_le_bytes();
         result[1..=2].copy_from_slice(&voltage_bytes);
         result[3] = self.output_current;
-        result[4] = self.discharge_compatitiblity.into(); // EVSE discharge compatitbility flag
-        result[5] = self.status.into();
+        result[4] = u8::from(self.discharge_compatitiblity) | self.reserved[0]; // EVSE discharge compatitbility flag
+        result[5] = u8::from(self.status) | self.reserved[1];
         result[6] = self.remaining_charging_time_10s_bit;
         result[7] = self.remaining_charging_time_1min_bit;
-        let id = raw_to_id(0x109);
-        T::new(id, &result)
+        result
+    }
+    fn from_payload(data: &[u8]) -> Result<Self, ChademoError> {
+        let data = data_sanity(data)?;
+        let status = X109Status::from(data[5]);
+        Ok(Self {
+            control_protocol_number_qc: data[0],
+            output_voltage: u16::from_le_bytes([data[1], data[2]]) as f32,
+            output_current: data[3],
+            discharge_compatitiblity: get_bit(data[4], 0),
+            status,
+            remaining_charging_time_10s_bit: data[6],
+            remaining_charging_time_1min_bit: data[7],
+            reserved: [data[4] & !0x01, data[5] & !u8::from(status)],
+        })
     }
+}
+
+impl X109 {
     pub fn new(control_protocol_number_qc: u8, discharge_compatitiblity: bool) -> Self {
         let status = X109Status {
             status_charger_stop_control: true,
@@ -406,32 +652,36 @@ impl<T: Frame> X109<T> {
             status,
             output_voltage: 0.0,
             output_current: 0,
-            phantom: PhantomData,
+            reserved: [0; 2],
         }
     }
-}
-
-impl<T> From<&T> for X109<T>
-where
-    T: Frame,
-{
-    fn from(frame: &T) -> X109<T> {
-        let data = data_sanity::<T>(frame, 0x109, 8);
-        Self {
-            control_protocol_number_qc: data[0],
-            output_voltage: u16::from_le_bytes([data[1], data[2]]) as f32,
-            output_current: data[3],
-            discharge_compatitiblity: data[4] == 1,
-            status: data[5].into(),
-            remaining_charging_time_10s_bit: data[6],
-            remaining_charging_time_1min_bit: data[7],
-            phantom: PhantomData,
+    pub fn control_protocol_number(&self) -> u8 {
+        self.control_protocol_number_qc
+    }
+    pub fn set_control_protocol_number(&mut self, protocol: ProtocolNumber) {
+        self.control_protocol_number_qc = protocol.into();
+    }
+    /// Encode remaining charging time into 109.6 (10 s units) or 109.7 (1 min units)
+    ///
+    /// When minutes are used 109.6 is set to 0xFF, values saturate at 0xFE.
+    pub fn set_remaining_charging_time(&mut self, seconds: u32, in_minutes: bool) {
+        match in_minutes {
+            true => {
+                self.remaining_charging_time_10s_bit = 0xFF;
+                self.remaining_charging_time_1min_bit = seconds.div_ceil(60).min(0xFE) as u8;
+            }
+            false => {
+                self.remaining_charging_time_10s_bit = seconds.div_ceil(10).min(0xFE) as u8;
+                self.remaining_charging_time_1min_bit = 0;
+            }
         }
     }
 }
 
 // Vehicle can frame
 #[derive(Default, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct X200 {
     /// Maximum discharge current that the vehicle permits to the EVSE.
     ///
@@ -458,32 +708,39 @@ pub struct X200 {
     ///
     /// However, in case of the vehicles before the V2H guideline 1.0, the unit of this value is kWh. Using the next expression, the EVSE converts a unit into %. Maximum charging rate for charging [%] = Maximum remaining battery capacity for charging [kWh] ÷ Total battery capacity [kWh] × 100 [%]. In addition, the EVSE cuts off a decimal and applies a unit conversion result. — When the EVSE receives 0, it shall not be used with the assumption that the value is not set. — The EVSE shall not be used until the switch (k) is turned on.
     pub max_remaining_capacity_for_charging: u8,
-    // phantom: PhantomData<T>,
+    /// Reserved bytes 200.1 to 200.3, kept for re-encoding
+    reserved: [u8; 3],
 }
 
-impl<T> From<&T> for X200
-where
-    T: Frame,
-{
-    fn from(frame: &T) -> X200 {
-        let data = data_sanity(frame, 0x200, 8);
-        Self {
+impl Payload for X200 {
+    const ID: u16 = 0x200;
+    fn to_payload(&self) -> [u8; 8] {
+        let mut data = [0u8; 8];
+        data[0] = 255 - self.maximum_discharge_current;
+        data[1..=3].copy_from_slice(&self.reserved);
+        [data[4], data[5]] = self.minimum_discharge_voltage.to_le_bytes();
+        data[6] = 255 - self.minimum_battery_discharge_level;
+        data[7] = self.max_remaining_capacity_for_charging;
+        data
+    }
+    fn from_payload(data: &[u8]) -> Result<Self, ChademoError> {
+        let data = data_sanity(data)?;
+        Ok(Self {
             maximum_discharge_current: 255 - data[0],
             minimum_discharge_voltage: u16::from_le_bytes(data[4..=5].try_into().unwrap()),
             minimum_battery_discharge_level: 255 - data[6],
             max_remaining_capacity_for_charging: data[7],
-            // phantom: PhantomData,
-        }
+            reserved: [data[1], data[2], data[3]],
+        })
     }
 }
 
 /// EVSE V2x
 
-#[derive(Debug, Clone, Copy)]
-pub struct X208<T>
-where
-    T: Frame,
-{
+#[derive(Debug, Default, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct X208 {
     /// The circuit current measured by the EVSE.
     pub discharge_current: u8,
     /// The minimum voltage with which the EVSE can operate.
@@ -492,22 +749,34 @@ where
     input_current: u8,
     /// The voltage with which the EVSE shall stop when the vehicle cannot stop at the minimum discharge voltage of the vehicle system due to a fault.
     lower_threshold_voltage: u16,
-    phantom: PhantomData<T>,
+    /// Reserved bytes 208.4 and 208.5, kept for re-encoding
+    reserved: [u8; 2],
 }
-impl<T> X208<T>
-where
-    T: Frame,
-{
-    pub fn to_can(&self) -> Option<T> {
+
+impl Payload for X208 {
+    const ID: u16 = 0x208;
+    fn to_payload(&self) -> [u8; 8] {
         let mut data = [0u8; 8];
 
         data[0] = 0xff - self.discharge_current;
         [data[1], data[2]] = self.input_voltage.to_le_bytes();
         data[3] = 0xff - self.input_current;
+        [data[4], data[5]] = self.reserved;
         [data[6], data[7]] = self.lower_threshold_voltage.to_le_bytes();
-        let id = raw_to_id(0x208);
-        T::new(id, &data)
+        data
+    }
+    fn from_payload(data: &[u8]) -> Result<Self, ChademoError> {
+        let data = data_sanity(data)?;
+        Ok(X208 {
+            discharge_current: 255 - data[0],
+            input_voltage: u16::from_le_bytes(data[1..=2].try_into().unwrap()),
+            input_current: 255 - data[3],
+            lower_threshold_voltage: u16::from_le_bytes(data[6..=7].try_into().unwrap()),
+            reserved: [data[4], data[5]],
+        })
     }
+}
+impl X208 {
     /// positive is discharge - discharge_current is real time, input_* are adjustable limits
     pub fn new(
         discharge_current: u8,
@@ -520,7 +789,7 @@ where
             input_voltage,
             input_current,
             lower_threshold_voltage,
-            phantom: PhantomData,
+            reserved: [0; 2],
         }
     }
 
@@ -552,63 +821,52 @@ where
     }
 }
 
-impl<T> From<&T> for X208<T>
-where
-    T: Frame,
-{
-    fn from(frame: &T) -> Self {
-        let data = data_sanity(frame, 0x208, 8);
-        X208 {
-            discharge_current: 255 - data[0],
-            input_voltage: u16::from_le_bytes(data[1..=2].try_into().unwrap()),
-            input_current: 255 - data[3],
-            lower_threshold_voltage: u16::from_le_bytes(data[6..=7].try_into().unwrap()),
-            phantom: PhantomData,
-        }
-    }
-}
-
-#[derive(Debug, Clone, Copy)]
-pub struct X209<T> {
-    /// Charge/dis charge sequence control number
+#[derive(Debug, Default, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct X209 {
+    /// 209.0 - Charge/discharge sequence control number
     sequence: u8,
     /// Remaining discharging time
+    #[cfg_attr(feature = "serde", serde(rename = "remaining_discharge_time"))]
     pub remaing_discharge_time: u16,
-    phantom: PhantomData<T>,
+    /// Reserved bytes 209.3 to 209.7, kept for re-encoding
+    reserved: [u8; 5],
 }
 
-impl<T> X209<T>
-where
-    T: Frame,
-{
-    pub fn to_can(&self) -> Option<T> {
+impl Payload for X209 {
+    const ID: u16 = 0x209;
+    fn to_payload(&self) -> [u8; 8] {
         let mut data = [0u8; 8];
 
         data[0] = self.sequence;
         [data[1], data[2]] = self.remaing_discharge_time.to_le_bytes();
-        let id = raw_to_id(0x209);
-        T::new(id, &data)
+        data[3..].copy_from_slice(&self.reserved);
+        data
     }
+    fn from_payload(data: &[u8]) -> Result<Self, ChademoError> {
+        let data = data_sanity(data)?;
+        Ok(Self {
+            sequence: data[0],
+            remaing_discharge_time: u16::from_le_bytes(data[1..=2].try_into().unwrap()),
+            reserved: data[3..].try_into().unwrap(),
+        })
+    }
+}
+
+impl X209 {
     pub fn new(sequence: u8, remaing_discharge_time: u16) -> Self {
         Self {
             sequence,
             remaing_discharge_time,
-            phantom: PhantomData,
+            reserved: [0; 5],
         }
     }
-}
-
-impl<T> From<&T> for X209<T>
-where
-    T: Frame,
-{
-    fn from(frame: &T) -> Self {
-        let data = data_sanity(frame, 0x209, 8);
-        Self {
-            sequence: data[0],
-            remaing_discharge_time: u16::from_le_bytes(data[1..=2].try_into().unwrap()),
-            phantom: PhantomData,
-        }
+    pub fn sequence(&self) -> u8 {
+        self.sequence
+    }
+    pub fn set_sequence(&mut self, sequence: u8) {
+        self.sequence = sequence;
     }
 }
 
@@ -618,80 +876,79 @@ fn get_bit(byte: u8, position: u8) -> bool {
 }
 
 #[inline]
-fn data_sanity<T>(frame: &T, id: u32, dlc: usize) -> &[u8]
-where
-    T: Frame,
-{
-    assert!(
-        frame.id() == raw_to_id(id as u16),
-        "CANFrame decoder error: Incorrect ID can frame"
-    );
-    assert!(
-        frame.data().len() == dlc,
-        "CANFrame decoder error: DLC for can frame is not 8"
-    );
-    frame.data()
+fn data_sanity(data: &[u8]) -> Result<&[u8; 8], ChademoError> {
+    data.try_into()
+        .map_err(|_| ChademoError::DecodeBadDlc(data.len()))
 }
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::interface::ChademoCanFrame;
     #[test]
     fn x109_test() {
-        let id = raw_to_id(0x109);
-        let frame = ChademoCanFrame::new(
-            id,
-            [0x02, 0x00, 0x00, 0x00, 0x01, 0x20, 0x00, 0x00].as_slice(),
-        )
-        .unwrap();
-        let x109 = X109::from(&frame);
+        let x109 = X109::from_payload(&[0x02, 0x00, 0x00, 0x00, 0x01, 0x20, 0x00, 0x00]).unwrap();
         assert!(!x109.status.status_vehicle_connector_lock);
         assert!(x109.status.status_charger_stop_control);
 
-        let frame = ChademoCanFrame::new(
-            id,
-            [0x02, 0x80, 0x01, 0x00, 0x01, 0x24, 0x00, 0x00].as_slice(),
-        )
-        .unwrap();
-        let x109 = X109::from(&frame);
+        let x109 = X109::from_payload(&[0x02, 0x80, 0x01, 0x00, 0x01, 0x24, 0x00, 0x00]).unwrap();
         assert!(x109.status.status_charger_stop_control);
 
-        let frame = ChademoCanFrame::new(
-            id,
-            [0x02, 0x80, 0x01, 0x00, 0x01, 0x05, 0x00, 0x00].as_slice(),
-        )
-        .unwrap();
-        let x109 = X109::from(&frame);
+        let x109 = X109::from_payload(&[0x02, 0x80, 0x01, 0x00, 0x01, 0x05, 0x00, 0x00]).unwrap();
         assert!(!x109.status.status_charger_stop_control);
         assert!(x109.status.status_station);
     }
     #[test]
+    fn round_trip_test() {
+        fn round_trip<F: Payload>(data: [u8; 8]) {
+            let decoded = F::from_payload(&data).unwrap();
+            assert_eq!(decoded.to_payload(), data, "H'{:X}", F::ID);
+        }
+        round_trip::<X100>([0x00, 0x11, 0x00, 0x00, 0xB3, 0x01, 0x64, 0x22]);
+        round_trip::<X101>([0x33, 0xFF, 0x3C, 0x00, 0x44, 0xDE, 0x00, 0x55]);
+        round_trip::<X102>([0x02, 0x9A, 0x01, 0x0E, 0xE1, 0xE1, 0x49, 0x66]);
+        round_trip::<X108>([0x01, 0xF4, 0x01, 0x0F, 0xB3, 0x01, 0x77, 0x88]);
+        round_trip::<X109>([0x02, 0x80, 0x01, 0x0F, 0x01, 0x05, 0xFF, 0x3C]);
+        for byte in 0..=u8::MAX {
+            round_trip::<X109>([0x02, 0x80, 0x01, 0x0F, byte, byte, 0xFF, 0x3C]);
+        }
+        round_trip::<X200>([0xFF, 0x01, 0x02, 0x03, 0xFA, 0x00, 0xFF, 0xFF]);
+        round_trip::<X208>([0xFE, 0xF4, 0x01, 0xEF, 0x99, 0xAA, 0xFA, 0x00]);
+        round_trip::<X209>([0x02, 0x10, 0x0E, 0x01, 0x02, 0x03, 0x04, 0x05]);
+    }
+    #[test]
+    fn decode_error_test() {
+        assert!(matches!(
+            X102::from_payload(&[0x02, 0x9A, 0x01]),
+            Err(ChademoError::DecodeBadDlc(3))
+        ));
+    }
+    #[test]
+    fn x101_test() {
+        let x101 = X101::from_payload(&[0x00, 0xFF, 0x3C, 0x00, 0x00, 0xDE, 0x00, 0x00]).unwrap();
+        assert_eq!(x101.max_charging_time(), Some(60 * 60));
+        assert!((x101.rated_battery_capacity() - 22.2).abs() < 0.01);
+
+        let x101 = X101::from_payload(&[0x00, 0x5A, 0x3C, 0x0A, 0x00, 0xF0, 0x00, 0x00]).unwrap();
+        assert_eq!(x101.max_charging_time(), Some(900));
+        assert_eq!(x101.estimated_charging_time(), 600);
+
+        assert_eq!(X101::default().max_charging_time(), None);
+        assert_eq!(X101::new(900, 0, 24.0).max_charging_time(), Some(900));
+        assert_eq!(
+            X101::new(90 * 60, 0, 24.0).max_charging_time(),
+            Some(90 * 60)
+        );
+    }
+    #[test]
     fn x102_test() {
-        let id = raw_to_id(0x102);
-        let frame = ChademoCanFrame::new(
-            id,
-            [0x02, 0x9A, 0x01, 0x00, 0x00, 0xC8, 0x56, 0x00].as_slice(),
-        )
-        .unwrap();
-        let x102: X102 = X102::from(&frame);
+        let x102 = X102::from_payload(&[0x02, 0x9A, 0x01, 0x00, 0x00, 0xC8, 0x56, 0x00]).unwrap();
         println!("{}", x102.status);
         assert!(!x102.contactors_closed());
 
-        let frame = ChademoCanFrame::new(
-            id,
-            [0x02, 0x9A, 0x01, 0x00, 0x00, 0xC9, 0x56, 0x00].as_slice(),
-        )
-        .unwrap();
-        let x102: X102 = X102::from(&frame);
+        let x102 = X102::from_payload(&[0x02, 0x9A, 0x01, 0x00, 0x00, 0xC9, 0x56, 0x00]).unwrap();
         assert!(x102.can_close_contactors());
         println!("{}", x102.status);
 
-        let frame = ChademoCanFrame::new(
-            id,
-            [0x02, 0x9A, 0x01, 0x00, 0x00, 0xC1, 0x56, 0x00].as_slice(),
-        )
-        .unwrap();
-        let x102 = X102::from(&frame);
+        let x102 = X102::from_payload(&[0x02, 0x9A, 0x01, 0x00, 0x00, 0xC1, 0x56, 0x00]).unwrap();
         assert!(x102.contactors_closed());
     }
 }
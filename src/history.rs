@@ -0,0 +1,191 @@
+//! Fixed-size history of the frames exchanged with the vehicle, for post-mortem analysis.
+//!
+//! [`FrameHistory`] keeps the last `N` RX/TX frames in a [`RingBuffer`] and copies them into a
+//! snapshot on the first fault or stop of a session, so the lead-up survives whatever follows.
+//! Snapshots export as `CanLogs/` CSV through [`crate::trace::write_csv`] or as
+//! [`TraceRecord::to_bytes`].
+use crate::trace::{Direction, TraceRecord};
+
+/// Last `N` items, the oldest is overwritten when full
+#[derive(Debug, Clone, Copy)]
+pub struct RingBuffer<T, const N: usize> {
+    items: [Option<T>; N],
+    /// Index of the oldest item
+    start: usize,
+    len: usize,
+}
+
+impl<T: Copy, const N: usize> Default for RingBuffer<T, N> {
+    fn default() -> Self {
+        Self {
+            items: [None; N],
+            start: 0,
+            len: 0,
+        }
+    }
+}
+
+impl<T: Copy, const N: usize> RingBuffer<T, N> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append `item`, returning the oldest one when it had to make room
+    pub fn push(&mut self, item: T) -> Option<T> {
+        if N == 0 {
+            return Some(item);
+        }
+        let oldest = self.items[(self.start + self.len) % N].replace(item);
+        match self.len < N {
+            true => self.len += 1,
+            false => self.start = (self.start + 1) % N,
+        }
+        oldest
+    }
+
+    /// Remove the oldest item
+    pub fn pop(&mut self) -> Option<T> {
+        let item = self.items.get_mut(self.start)?.take()?;
+        self.start = (self.start + 1) % N;
+        self.len -= 1;
+        Some(item)
+    }
+
+    /// Oldest first
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        (0..self.len).filter_map(move |i| self.items[(self.start + i) % N].as_ref())
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// RX/TX frames with their time stamps plus a snapshot frozen on the first fault or stop
+#[derive(Debug, Clone, Default)]
+pub struct FrameHistory<const N: usize> {
+    frames: RingBuffer<TraceRecord, N>,
+    snapshot: Option<RingBuffer<TraceRecord, N>>,
+}
+
+impl<const N: usize> FrameHistory<N> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, timestamp_ms: u64, dir: Direction, id: u16, data: [u8; 8]) {
+        let mut record = TraceRecord::new(timestamp_ms * 1000, id, data);
+        record.dir = dir;
+        self.frames.push(record);
+    }
+
+    /// Frames recorded so far, oldest first
+    pub fn frames(&self) -> impl Iterator<Item = &TraceRecord> {
+        self.frames.iter()
+    }
+
+    /// Copy the present frames into the snapshot, unless one is already held
+    pub fn freeze(&mut self) {
+        if self.snapshot.is_none() {
+            self.snapshot = Some(self.frames);
+        }
+    }
+
+    /// Frames leading up to the first fault or stop since [`Self::clear_snapshot`]
+    pub fn snapshot(&self) -> Option<impl Iterator<Item = &TraceRecord>> {
+        self.snapshot.as_ref().map(RingBuffer::iter)
+    }
+
+    pub fn clear_snapshot(&mut self) {
+        self.snapshot = None;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::error::StopReason;
+    use crate::trace;
+    use crate::Chademo;
+
+    #[test]
+    fn ring_buffer_test() {
+        let mut ring: RingBuffer<u8, 3> = RingBuffer::new();
+        assert_eq!(ring.pop(), None);
+        assert_eq!(ring.push(1), None);
+        assert_eq!(ring.push(2), None);
+        assert_eq!(ring.push(3), None);
+        assert!(ring.is_full());
+        assert_eq!(ring.push(4), Some(1));
+        assert_eq!(ring.iter().copied().collect::<Vec<_>>(), [2, 3, 4]);
+        assert_eq!(ring.pop(), Some(2));
+        assert_eq!(ring.push(5), None);
+        assert_eq!(ring.iter().copied().collect::<Vec<_>>(), [3, 4, 5]);
+        ring.clear();
+        assert!(ring.is_empty());
+    }
+
+    #[test]
+    fn snapshot_test() {
+        let mut chademo = Chademo::new(16);
+        let x102 = |faults: u8| [0x2, 0x9A, 0x1, 0x0E, faults, 0x1, 0x56, 0x0];
+        for now in (0..10_000).step_by(100) {
+            chademo.tick(now);
+            chademo.decode_payload(0x102, &x102(0)).unwrap();
+            chademo.record_tx(now);
+        }
+        assert!(chademo.history.snapshot().is_none());
+        // 102.4.4 freezes the lead-up with the faulty frame last
+        chademo.tick(10_000);
+        chademo.decode_payload(0x102, &x102(0x01)).unwrap();
+        for now in (10_100..12_000).step_by(100) {
+            chademo.tick(now);
+            chademo.decode_payload(0x102, &x102(0)).unwrap();
+        }
+        let snapshot: Vec<_> = chademo.history.snapshot().unwrap().copied().collect();
+        assert_eq!(snapshot.len(), crate::HISTORY_FRAMES);
+        let last = snapshot.last().unwrap();
+        assert_eq!((last.timestamp_ms(), last.data[4]), (10_000, 0x01));
+        assert!(snapshot.iter().any(|r| r.dir == Direction::Tx));
+
+        // CSV and raw exports replay to the same frames
+        let mut csv = String::new();
+        trace::write_csv(&mut csv, &snapshot).unwrap();
+        let replayed: Vec<_> = trace::records(&csv).map(Result::unwrap).collect();
+        assert_eq!(replayed, snapshot);
+        let raw: Vec<_> = snapshot.iter().map(TraceRecord::to_bytes).collect();
+        assert!(raw
+            .iter()
+            .map(TraceRecord::from_bytes)
+            .eq(snapshot.iter().copied()));
+
+        // A new session drops it, a normal stop freezes too
+        chademo.charge_start();
+        assert!(chademo.history.snapshot().is_none());
+        chademo.tick(12_000);
+        chademo.decode_payload(0x102, &x102(0)).unwrap();
+        chademo.request_stop(StopReason::User);
+        let last = *chademo.history.snapshot().unwrap().last().unwrap();
+        assert_eq!(last.timestamp_ms(), 12_000);
+
+        // The fault of a later session is captured after the normal stop
+        chademo.charge_stop();
+        chademo.charge_start();
+        chademo.tick(13_000);
+        chademo.decode_payload(0x102, &x102(0x01)).unwrap();
+        let last = *chademo.history.snapshot().unwrap().last().unwrap();
+        assert_eq!((last.timestamp_ms(), last.data[4]), (13_000, 0x01));
+    }
+}
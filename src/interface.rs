@@ -1,22 +1,5 @@
 use core::ops::{Deref, DerefMut};
 
-#[cfg(feature = "eh1")]
-use embedded_can::{Frame, Id, StandardId};
-#[cfg(feature = "eh0")]
-use embedded_hal::can::{Frame, Id, StandardId};
-
-use crate::error::ChademoError;
-
-pub(crate) fn raw_to_id(id: u16) -> Id {
-    Id::Standard(StandardId::new(id).unwrap())
-}
-pub(crate) fn standard_id_to_raw(id: Id) -> Result<u16, ChademoError> {
-    match id {
-        Id::Standard(id) => Ok(id.as_raw()),
-        Id::Extended(_) => Err(ChademoError::DecodeBadIdExt),
-    }
-}
-
 /// CAN data structure from BXcan crate
 ///  https://github.com/stm32-rs/bxcan/blob/3fc7a0e81975d4f25e61e0da81cd9e7a5e969e81/src/frame.rs#L157C18-L157C18
 /// Payload of a CAN data frame.
@@ -25,12 +8,14 @@ pub(crate) fn standard_id_to_raw(id: Id) -> Result<u16, ChademoError> {
 ///
 /// `Data` implements `From<[u8; N]>` for all `N` up to 8, which provides a convenient lossless
 /// conversion from fixed-length arrays.
+#[allow(dead_code)]
 #[derive(Debug, Copy, Clone)]
 pub struct Data {
     pub(crate) len: u8,
     pub(crate) bytes: [u8; 8],
 }
 
+#[allow(dead_code)]
 impl Data {
     /// Creates a data payload from a raw byte slice.
     ///
@@ -82,6 +67,7 @@ impl AsMut<[u8]> for Data {
     }
 }
 
+#[allow(dead_code)]
 pub struct ChademoCanFrame {
     data: Data,
     id: u32,
@@ -89,66 +75,208 @@ pub struct ChademoCanFrame {
     err: bool,
 }
 
-// #[cfg(any(feature=["eh0", "eh1"]))]
-impl Frame for ChademoCanFrame {
-    fn new(id: impl Into<Id>, data: &[u8]) -> Option<Self> {
-        let id = id.into();
-        let id = match id {
-            // Id::Extended(foo) => foo.as_raw(),
-            Id::Standard(id) => id.as_raw() as u32,
-            _ => return None,
-        };
-
-        Data::new(data).map(|data| {
-            Self {
-                data,
-                id,
-                // dlc: data.len() as u8,
-                rtr: false,
-                err: false,
+/// Generates the `Frame` adapter for one CAN trait crate, keeping `eh0` and `eh1` in step
+#[allow(unused_macros)]
+macro_rules! frame_adapter {
+    ($($can:ident)::+) => {
+        use $($can)::+::{Frame, Id, StandardId};
+
+        use crate::error::ChademoError;
+        use crate::frames::Payload;
+        use crate::interface::{ChademoCanFrame, Data};
+        use crate::monitor::{ChademoMonitor, Phase};
+        use crate::proxy::{ChademoProxy, Forward, Rewrite};
+        use crate::Chademo;
+
+        fn raw_to_id(id: u16) -> Id {
+            Id::Standard(StandardId::new(id).unwrap())
+        }
+        fn standard_id_to_raw(id: Id) -> Result<u16, ChademoError> {
+            match id {
+                Id::Standard(id) => Ok(id.as_raw()),
+                Id::Extended(_) => Err(ChademoError::DecodeBadIdExt),
             }
-        })
-    }
+        }
 
-    fn new_remote(_id: impl Into<Id>, _dlc: usize) -> Option<Self> {
-        None
-    }
+        /// `Frame` adapter for the raw [`Payload`] codecs
+        pub trait FrameCodec: Payload {
+            fn to_can<T: Frame>(&self) -> Option<T> {
+                T::new(raw_to_id(Self::ID), &self.to_payload())
+            }
+            fn from_can<T: Frame>(frame: &T) -> Result<Self, ChademoError> {
+                match standard_id_to_raw(frame.id())? {
+                    id if id == Self::ID => Self::from_payload(frame.data()),
+                    bad_id => Err(ChademoError::DecodeBadId(bad_id)),
+                }
+            }
+        }
+        impl<P: Payload> FrameCodec for P {}
 
-    fn is_extended(&self) -> bool {
-        self.err
-    }
+        /// `Frame` adapter for [`Chademo`]
+        pub trait ChademoFrames {
+            fn decode<T: Frame>(&mut self, frame: T) -> Result<(), ChademoError>;
+            fn tx_frames<T: Frame>(&self) -> [Option<T>; 4];
+        }
+        impl ChademoFrames for Chademo {
+            fn decode<T: Frame>(&mut self, frame: T) -> Result<(), ChademoError> {
+                self.decode_payload(standard_id_to_raw(frame.id())?, frame.data())
+            }
+            fn tx_frames<T: Frame>(&self) -> [Option<T>; 4] {
+                self.tx_payloads()
+                    .map(|(id, data)| T::new(raw_to_id(id), &data))
+            }
+        }
 
-    fn is_remote_frame(&self) -> bool {
-        self.rtr
-    }
+        /// `Frame` adapter for [`ChademoMonitor`]
+        pub trait MonitorFrames {
+            fn decode<T: Frame>(&mut self, frame: T) -> Result<Phase, ChademoError>;
+        }
+        impl MonitorFrames for ChademoMonitor {
+            fn decode<T: Frame>(&mut self, frame: T) -> Result<Phase, ChademoError> {
+                self.decode_payload(standard_id_to_raw(frame.id())?, frame.data())
+            }
+        }
 
-    fn id(&self) -> Id {
-        Id::Standard(StandardId::new(self.id as u16).expect("StandardID construction failed"))
-    }
+        /// `Frame` adapter for [`ChademoProxy`]
+        pub trait ProxyFrames {
+            /// Frame to send on the other bus, rewritten when a rule changed it
+            ///
+            /// On error the original frame is handed back with it, e.g. to forward it unchanged.
+            fn forward_frame<T: Frame>(
+                &mut self,
+                frame: T,
+            ) -> Result<(T, Forward), (T, ChademoError)>;
+        }
+        impl<R: Rewrite> ProxyFrames for ChademoProxy<R> {
+            fn forward_frame<T: Frame>(
+                &mut self,
+                frame: T,
+            ) -> Result<(T, Forward), (T, ChademoError)> {
+                let Id::Standard(id) = frame.id() else {
+                    return Ok((frame, Forward::Unchanged));
+                };
+                match self.forward(id.as_raw(), frame.data()) {
+                    Ok(Forward::Rewritten(log)) => match T::new(id, &log.after) {
+                        Some(rewritten) => Ok((rewritten, Forward::Rewritten(log))),
+                        None => Err((frame, ChademoError::EncodeFrame(id.as_raw()))),
+                    },
+                    Ok(Forward::Unchanged) => Ok((frame, Forward::Unchanged)),
+                    Err(e) => Err((frame, e)),
+                }
+            }
+        }
 
-    fn dlc(&self) -> usize {
-        self.data.len()
-    }
+        impl Frame for ChademoCanFrame {
+            fn new(id: impl Into<Id>, data: &[u8]) -> Option<Self> {
+                let id = id.into();
+                let id = match id {
+                    // Id::Extended(foo) => foo.as_raw(),
+                    Id::Standard(id) => id.as_raw() as u32,
+                    _ => return None,
+                };
 
-    fn data(&self) -> &[u8] {
-        &self.data
-    }
-}
+                Data::new(data).map(|data| {
+                    Self {
+                        data,
+                        id,
+                        // dlc: data.len() as u8,
+                        rtr: false,
+                        err: false,
+                    }
+                })
+            }
+
+            fn new_remote(_id: impl Into<Id>, _dlc: usize) -> Option<Self> {
+                None
+            }
+
+            fn is_extended(&self) -> bool {
+                self.err
+            }
+
+            fn is_remote_frame(&self) -> bool {
+                self.rtr
+            }
+
+            fn id(&self) -> Id {
+                Id::Standard(StandardId::new(self.id as u16).expect("StandardID construction failed"))
+            }
+
+            fn dlc(&self) -> usize {
+                self.data.len()
+            }
 
-#[cfg(feature = "test")]
-impl CanFrameInterface for ChademoCanFrame {
-    fn new(id: u32, data: &[u8]) -> Self {
-        Self {
-            data: Data::new(data).unwrap(),
-            id,
-            rtr: false,
-            err: false,
+            fn data(&self) -> &[u8] {
+                &self.data
+            }
         }
-    }
-    fn id(&self) -> u32 {
-        self.id
-    }
-    fn data(&self) -> &[u8] {
-        &self.data
-    }
+
+        #[cfg(test)]
+        mod test {
+            use super::*;
+            use crate::frames::{X102, X208};
+
+            #[test]
+            fn decode_test() {
+                let frame = ChademoCanFrame::new(
+                    raw_to_id(0x102),
+                    [0x2, 0x9A, 0x1, 0x0E, 0x0, 0xC1, 0x56, 0x0].as_slice(),
+                )
+                .unwrap();
+                let mut chademo = Chademo::new(15);
+                chademo.decode(frame).unwrap();
+                assert_eq!(chademo.soc(), &86);
+                let frames: [Option<ChademoCanFrame>; 4] = chademo.tx_frames();
+                assert!(frames.iter().all(Option::is_some));
+
+                let frame = ChademoCanFrame::new(raw_to_id(0x109), [0u8; 8].as_slice()).unwrap();
+                assert!(matches!(chademo.decode(frame), Err(ChademoError::DecodeBadId(0x109))));
+
+                let mut monitor = ChademoMonitor::new();
+                let frame = ChademoCanFrame::new(raw_to_id(0x109), [0u8; 8].as_slice()).unwrap();
+                assert_eq!(MonitorFrames::decode(&mut monitor, frame).unwrap(), Phase::Initialisation);
+            }
+            #[test]
+            fn proxy_test() {
+                let mut proxy = ChademoProxy::new(crate::proxy::ClampChargingCurrent(10));
+                let frame = ChademoCanFrame::new(
+                    raw_to_id(0x102),
+                    [0x2, 0x9A, 0x1, 0x0E, 0x0, 0xC1, 0x56, 0x0].as_slice(),
+                )
+                .unwrap();
+                let Ok((frame, forward)) = proxy.forward_frame(frame) else {
+                    panic!("H'102 not forwarded");
+                };
+                assert!(matches!(forward, Forward::Rewritten(_)));
+                assert_eq!(X102::from_can(&frame).unwrap().charging_current_request, 10);
+
+                // A frame the proxy cannot decode comes back for forwarding as is
+                let short = ChademoCanFrame::new(raw_to_id(0x102), [0x2, 0x9A].as_slice()).unwrap();
+                let Err((frame, e)) = proxy.forward_frame(short) else {
+                    panic!("short H'102 decoded");
+                };
+                assert!(matches!(e, ChademoError::DecodeBadDlc(2)));
+                assert_eq!(frame.data(), [0x2, 0x9A]);
+            }
+            #[test]
+            fn codec_test() {
+                let cf: ChademoCanFrame = X208::new(1, 500, 16, 250).to_can().unwrap();
+                assert_eq!(cf.id(), raw_to_id(0x208));
+                assert_eq!(X208::from_can(&cf).unwrap().get_input_current(), 16);
+                assert!(matches!(X102::from_can(&cf), Err(ChademoError::DecodeBadId(0x208))));
+            }
+        }
+    };
+}
+
+#[cfg(feature = "eh0")]
+pub mod eh0 {
+    //! `embedded-hal` 0.2 `can::Frame` adapter
+    frame_adapter!(embedded_hal::can);
+}
+
+#[cfg(feature = "eh1")]
+pub mod eh1 {
+    //! `embedded-can` 0.4 `Frame` adapter
+    frame_adapter!(embedded_can);
 }
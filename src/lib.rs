@@ -1,52 +1,158 @@
-#![feature(error_in_core)]
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
 /// Notes from:
 /// IEEE Std 2030.1.1-2021
 /// IEEE Standard for Technical Specifications of a DC Quick Charger for Use with Electric Vehicles
 use frames::*;
-use interface::standard_id_to_raw;
+use profile::{ProfileRegistry, Quirks};
 
+#[macro_use]
+mod log;
+
+pub mod battery;
+pub mod compliance;
+#[cfg(feature = "async")]
+pub mod driver;
+pub mod emulator;
 pub mod error;
+pub mod event;
+pub mod evse;
+#[cfg(any(test, feature = "sim"))]
+pub mod fault;
 pub mod frames;
+pub mod history;
 mod interface;
+pub mod meter;
+pub mod monitor;
+pub mod profile;
+pub mod proxy;
+#[cfg(feature = "scenario")]
+pub mod scenario;
+#[cfg(any(test, feature = "sim"))]
+pub mod sim;
+#[cfg(feature = "socketcan")]
+pub mod socketcan;
+pub mod trace;
+
+#[cfg(feature = "eh0")]
+pub use interface::eh0;
+#[cfg(feature = "eh1")]
+pub use interface::eh1;
+
+/// Frames kept in [`Chademo::history`]
+pub const HISTORY_FRAMES: usize = 32;
+/// Events kept until drained with [`Chademo::events`], the oldest are dropped first
+pub const EVENT_QUEUE: usize = 16;
 
 #[derive(Clone, Debug)]
-pub struct Chademo<T>
-where
-    T: Frame,
-{
+pub struct Chademo {
     pub x100: X100,
     pub x101: X101,
     pub x102: X102,
-    pub x108: X108<T>,
-    pub x109: X109<T>,
+    pub x108: X108,
+    pub x109: X109,
     pub x200: X200,
-    pub x208: X208<T>,
-    pub x209: X209<T>,
+    pub x208: X208,
+    pub x209: X209,
+    /// Highest protocol offered by the EVSE in 109.0
+    evse_protocol: ProtocolNumber,
+    /// Protocol agreed with the vehicle after the first 102.0
+    protocol: Option<ProtocolNumber>,
+    max_amps: u8,
+    /// EVSE charging current limit, caps 108.3 and the [`evse::Evse`] output
+    charge_limit: u8,
+    /// Timestamp of the first [`Chademo::tick`] after 109.5.0 was set
+    charge_started_ms: Option<u64>,
+    /// User requested SoC at which charging is expected to end
+    target_soc: Option<u8>,
+    /// Name of the [`profile::VehicleProfile`] the quirks came from
+    profile: Option<&'static str>,
+    quirks: Quirks,
+    /// First 200.0 received and whether the vehicle updated it since
+    initial_discharge_limit: Option<u8>,
+    discharge_limit_updated: bool,
+    /// Cause of the latest move into stop control
+    stop_reason: Option<error::StopReason>,
+    /// Last decoded and sent frames, frozen on the first fault or stop of a session
+    pub history: history::FrameHistory<HISTORY_FRAMES>,
+    /// Latest [`Chademo::tick`] time, stamps the frames in [`Chademo::history`]
+    now_ms: u64,
+    /// Changes seen by [`Chademo::decode_payload`] not yet drained
+    events: history::RingBuffer<event::Event, EVENT_QUEUE>,
+    x102_received: bool,
 }
 
-impl<T> std::fmt::Display for Chademo<T>
-where
-    T: Frame,
-{
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        let x102 = format!("{}", self.x102_status());
-        let x109 = format!("{}", self.x109_status());
+/// Frames and session state of a [`Chademo`] for dashboards and backends
+///
+/// Serializable without `alloc` with the `serde` feature. [`Chademo::history`] and the
+/// profile name are left out, restoring with [`Chademo::from`] starts them afresh.
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Snapshot {
+    pub x100: X100,
+    pub x101: X101,
+    pub x102: X102,
+    pub x108: X108,
+    pub x109: X109,
+    pub x200: X200,
+    pub x208: X208,
+    pub x209: X209,
+    pub evse_protocol: ProtocolNumber,
+    pub protocol: Option<ProtocolNumber>,
+    pub max_amps: u8,
+    pub charge_limit: u8,
+    pub charge_started_ms: Option<u64>,
+    pub target_soc: Option<u8>,
+    pub quirks: Quirks,
+    pub stop_reason: Option<error::StopReason>,
+}
+
+impl From<Snapshot> for Chademo {
+    fn from(snapshot: Snapshot) -> Self {
+        Self {
+            x100: snapshot.x100,
+            x101: snapshot.x101,
+            x102: snapshot.x102,
+            x108: snapshot.x108,
+            x109: snapshot.x109,
+            x200: snapshot.x200,
+            x208: snapshot.x208,
+            x209: snapshot.x209,
+            evse_protocol: snapshot.evse_protocol,
+            protocol: snapshot.protocol,
+            charge_limit: snapshot.charge_limit,
+            charge_started_ms: snapshot.charge_started_ms,
+            target_soc: snapshot.target_soc,
+            quirks: snapshot.quirks,
+            stop_reason: snapshot.stop_reason,
+            ..Chademo::new(snapshot.max_amps)
+        }
+    }
+}
+
+impl core::fmt::Display for Chademo {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let mask = match self.discharge_limit_masked() {
+            true => " masked",
+            false => "",
+        };
         write!(
             f,
-            "x102: status {}\nx109: status {}\nV2x: Max Dis: (-{}A {}%) Chg: {}A )",
-            x102,
-            x109,
+            "x102: status {}\nx109: status {}\nV2x: Max Dis: (-{}A{} {}%) Chg: {}A )",
+            self.x102_status(),
+            self.x109_status(),
             self.requested_discharging_amps(),
+            mask,
             self.max_remaining_capacity_for_charging(),
             self.requested_charging_amps(),
-        )
+        )?;
+        match self.stop_reason {
+            Some(reason) => write!(f, "\nstop: {reason}"),
+            None => Ok(()),
+        }
     }
 }
 
-impl<T> Chademo<T>
-where
-    T: Frame,
-{
+impl Chademo {
     pub fn new(max_amps: u8) -> Self {
         Self {
             //EV decode
@@ -59,51 +165,323 @@ where
             x108: X108::new(max_amps, 500, true, 435),
             x208: X208::new(0, 500, max_amps, 250),
             x209: X209::new(2, 0),
+            evse_protocol: ProtocolNumber::V1_0,
+            protocol: None,
+            max_amps,
+            charge_limit: max_amps,
+            charge_started_ms: None,
+            target_soc: None,
+            profile: None,
+            quirks: Quirks::default(),
+            initial_discharge_limit: None,
+            discharge_limit_updated: false,
+            stop_reason: None,
+            history: history::FrameHistory::new(),
+            now_ms: 0,
+            events: history::RingBuffer::new(),
+            x102_received: false,
         }
     }
 
-    pub fn decode(&mut self, frame: T) -> Result<(), error::ChademoError> {
-        match standard_id_to_raw(frame.id())? {
-            0x100 => self.x100 = X100::from(&frame),
-            0x101 => self.x101 = X101::from(&frame),
-            0x102 => self.x102 = X102::from(&frame),
-            0x200 => self.x200 = X200::from(&frame),
+    /// Decode a raw vehicle frame, CAN driver adapters forward here
+    ///
+    /// Decoded frames go into [`Self::history`] stamped with the latest [`Self::tick`] time,
+    /// a 102.4 fault freezes it.
+    pub fn decode_payload(&mut self, id: u16, data: &[u8]) -> Result<(), error::ChademoError> {
+        let previous = self.x102_received.then_some(self.x102);
+        let discharge_amps = self.requested_discharging_amps() as u8;
+        let had_fault = self.x102.fault();
+        let result = self.decode_vehicle_frame(id, data);
+        match (&result, data.try_into()) {
+            (Ok(()), Ok(data)) => {
+                let now = self.now_ms;
+                self.history.record(now, trace::Direction::Rx, id, data);
+                self.queue_events(previous, discharge_amps);
+            }
+            (Err(e), _) => warn!("H'{=u16:X} decode failed: {}", id, e),
+            _ => {}
+        }
+        if id == X102::ID && self.x102.fault() {
+            if !had_fault {
+                error!("102.4 battery fault {}", self.x102.faults());
+            }
+            self.history.freeze();
+        }
+        result
+    }
+    fn queue_events(&mut self, previous: Option<X102>, discharge_amps: u8) {
+        let events = &mut self.events;
+        if self.x102_received {
+            event::Event::x102_changes(previous.as_ref(), &self.x102, |event| {
+                events.push(event);
+            });
+        }
+        let amps = self.requested_discharging_amps() as u8;
+        if let Some(event) = event::Event::discharge_change(discharge_amps, amps) {
+            self.events.push(event);
+        }
+    }
+    /// Drain the [`event::Event`]s queued by [`Self::decode_payload`], oldest first
+    pub fn events(&mut self) -> impl Iterator<Item = event::Event> + '_ {
+        core::iter::from_fn(|| self.events.pop())
+    }
+    fn decode_vehicle_frame(&mut self, id: u16, data: &[u8]) -> Result<(), error::ChademoError> {
+        match id {
+            X100::ID => self.x100 = X100::from_payload(data)?,
+            X101::ID => self.x101 = X101::from_payload(data)?,
+            X102::ID => {
+                self.x102 = X102::from_payload(data)?;
+                self.x102_received = true;
+                self.negotiate_protocol()?;
+            }
+            X200::ID => {
+                self.x200 = X200::from_payload(data)?;
+                let limit = self.x200.maximum_discharge_current;
+                let initial = *self.initial_discharge_limit.get_or_insert(limit);
+                self.discharge_limit_updated |= limit != initial;
+            }
             bad_id => return Err(error::ChademoError::DecodeBadId(bad_id)),
         };
         Ok(())
     }
+    /// Service the session timers with a caller-supplied monotonic millisecond clock
+    ///
+    /// Moves to stop control once the vehicle's maximum charging time (101.1-2) has elapsed.
+    pub fn tick(&mut self, now_ms: u64) {
+        self.now_ms = now_ms;
+        if !self.x109.status.status_station {
+            self.charge_started_ms = None;
+            return;
+        }
+        let started = *self.charge_started_ms.get_or_insert(now_ms);
+        let elapsed = (now_ms.saturating_sub(started) / 1000) as u32;
+        let time_left = self
+            .x101
+            .max_charging_time()
+            .map(|limit| limit.saturating_sub(elapsed));
+        if time_left == Some(0) {
+            self.request_stop(error::StopReason::MaxChargingTime);
+        }
+        if self.x109.status.status_charger_stop_control {
+            return;
+        }
+        if let Some(remaining) = self.remaining_charging_time() {
+            let remaining = time_left.map_or(remaining, |left| remaining.min(left));
+            let in_minutes = self.active_protocol().remaining_time_in_minutes();
+            self.x109.set_remaining_charging_time(remaining, in_minutes);
+        }
+    }
+    /// Estimated seconds to reach [`Self::target_soc`] at the present output power
+    ///
+    /// `None` until the rated capacity (101.5-6) is known and current is flowing.
+    pub fn remaining_charging_time(&self) -> Option<u32> {
+        let capacity = self.x101.rated_battery_capacity();
+        let power = self.x109.output_voltage * self.x109.output_current as f32;
+        if capacity <= 0.0 || power <= 0.0 {
+            return None;
+        }
+        let soc_to_go = self.target_soc().saturating_sub(self.x102.state_of_charge) as f32;
+        let watt_hours = capacity * 1000.0 * soc_to_go / 100.0;
+        Some((watt_hours / power * 3600.0) as u32)
+    }
+    /// SoC the charge is expected to stop at
+    ///
+    /// Lowest of the user target and the vehicle's 200.7 charge limit, 100 % when neither is set.
+    pub fn target_soc(&self) -> u8 {
+        let vehicle = match self.max_remaining_capacity_for_charging() as u8 {
+            0 => 100,
+            limit => limit,
+        };
+        self.target_soc.unwrap_or(100).min(vehicle)
+    }
+    pub fn set_target_soc(&mut self, soc: Option<u8>) {
+        self.target_soc = soc;
+    }
+    /// Milliseconds spent charging as of `now_ms`, 0 outside a charge
+    pub fn charging_time(&self, now_ms: u64) -> u64 {
+        self.charge_started_ms
+            .map(|started| now_ms.saturating_sub(started))
+            .unwrap_or_default()
+    }
+    /// Pick the highest protocol both sides support, incompatible vehicles get an error stop
+    fn negotiate_protocol(&mut self) -> Result<(), error::ChademoError> {
+        let evse = self
+            .quirks
+            .max_protocol
+            .map_or(self.evse_protocol, |max| max.min(self.evse_protocol));
+        match ProtocolNumber::negotiate(self.x102.control_protocol_number_ev, evse) {
+            Ok(protocol) => {
+                self.protocol = Some(protocol);
+                self.x109.set_control_protocol_number(protocol);
+                Ok(())
+            }
+            Err(e) => {
+                self.protocol = None;
+                self.x109.status.fault_battery_incompatibility = true;
+                let protocol = self.x102.control_protocol_number_ev;
+                self.request_stop(error::StopReason::IncompatibleProtocol(protocol));
+                Err(e)
+            }
+        }
+    }
+    /// Protocol agreed with the vehicle, `None` until a valid 102.0 is received
+    pub fn protocol(&self) -> Option<ProtocolNumber> {
+        self.protocol
+    }
+    /// Highest protocol the EVSE offers, defaults to [`ProtocolNumber::V1_0`]
+    pub fn set_evse_protocol(&mut self, protocol: ProtocolNumber) {
+        self.evse_protocol = protocol;
+        if self.protocol.is_none() {
+            self.x109.set_control_protocol_number(protocol);
+        }
+    }
+    /// Apply the quirks of the first profile in `registry` matching H'100/H'101/H'102
+    ///
+    /// Vehicles no profile matches get spec behaviour. Returns the profile name.
+    pub fn identify<const N: usize>(
+        &mut self,
+        registry: &ProfileRegistry<N>,
+    ) -> Option<&'static str> {
+        let profile = registry.identify(&self.x100, &self.x101, &self.x102);
+        self.profile = profile.map(|profile| profile.name);
+        self.set_quirks(profile.map(|profile| profile.quirks).unwrap_or_default());
+        self.profile
+    }
+    /// Profile applied by [`Self::identify`]
+    pub fn profile(&self) -> Option<&'static str> {
+        self.profile
+    }
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+    /// Workarounds for the connected vehicle, the protocol is renegotiated if already agreed
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+        if self.protocol.is_some() {
+            let _ = self.negotiate_protocol();
+        }
+    }
+    /// Agreed protocol, or the EVSE protocol before negotiation
+    fn active_protocol(&self) -> ProtocolNumber {
+        self.protocol.unwrap_or(self.evse_protocol)
+    }
     /// Flag to EV that charge has been cancelled
     /// Sets 109.5.5 high
     pub fn request_stop_charge(&mut self) {
-        // change status of x109
+        self.request_stop(error::StopReason::User);
+    }
+    /// Set 109.5.5 and record why
+    ///
+    /// The reason is kept when stop control was already set for an earlier one.
+    pub fn request_stop(&mut self, reason: error::StopReason) {
+        if !self.x109.status.status_charger_stop_control || self.stop_reason.is_none() {
+            warn!("109.5.5 stop control set: {}", reason);
+            self.stop_reason = Some(reason);
+            self.history.freeze();
+        }
         self.x109.status.status_charger_stop_control = true;
     }
+    /// Cause of the latest stop control, kept after the session ends
+    pub fn stop_reason(&self) -> Option<error::StopReason> {
+        self.stop_reason
+    }
+    /// Present frames and session state, see [`Snapshot`]
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            x100: self.x100,
+            x101: self.x101,
+            x102: self.x102,
+            x108: self.x108,
+            x109: self.x109,
+            x200: self.x200,
+            x208: self.x208,
+            x209: self.x209,
+            evse_protocol: self.evse_protocol,
+            protocol: self.protocol,
+            max_amps: self.max_amps,
+            charge_limit: self.charge_limit,
+            charge_started_ms: self.charge_started_ms,
+            target_soc: self.target_soc,
+            quirks: self.quirks,
+            stop_reason: self.stop_reason,
+        }
+    }
+    /// Charger malfunction: sets 109.5.1 and stops
+    pub fn station_fault(&mut self) {
+        error!("109.5.1 charger malfunction");
+        self.x109.status.fault_station_malfunction = true;
+        self.request_stop(error::StopReason::StationFault);
+    }
+    /// Charge/discharge sequence control number sent in 209.0
+    pub fn sequence(&self) -> u8 {
+        self.x209.sequence()
+    }
+    /// Set 209.0, nothing changes it on its own
+    ///
+    /// [`Chademo::new`] sends 0x02, what the charger in the Leaf capture sends in every H'209
+    /// from preparation through stop.
+    pub fn set_sequence(&mut self, sequence: u8) {
+        if sequence != self.sequence() {
+            debug!(
+                "209.0 sequence {=u8:#X} -> {=u8:#X}",
+                self.sequence(),
+                sequence
+            );
+            self.x209.set_sequence(sequence)
+        }
+    }
     pub fn x102_status(&self) -> X102Status {
         self.x102.status
     }
     pub fn x109_status(&self) -> X109Status {
         self.x109.status
     }
-    pub fn tx_frames(&self) -> [Option<T>; 4] {
+    /// Add the frames [`Self::tx_payloads`] returns to [`Self::history`] when sending them
+    pub fn record_tx(&mut self, now_ms: u64) {
+        for (id, data) in self.tx_payloads() {
+            self.history.record(now_ms, trace::Direction::Tx, id, data);
+        }
+    }
+    /// Raw EVSE frames as `(id, data)`
+    ///
+    /// H'208/H'209 go out under every protocol, see [`ProtocolNumber`].
+    pub fn tx_payloads(&self) -> [(u16, [u8; 8]); 4] {
         [
-            self.x108.to_can(),
-            self.x109.to_can(),
-            self.x208.to_can(),
-            self.x209.to_can(),
+            (X108::ID, self.x108.to_payload()),
+            (X109::ID, self.x109.to_payload()),
+            (X208::ID, self.x208.to_payload()),
+            (X209::ID, self.x209.to_payload()),
         ]
     }
+    /// Charge limit when positive, EVSE input current (208.3) when negative
+    ///
+    /// No longer writes the present output current 109.3: callers driving [`Chademo`] without
+    /// [`evse::Evse`] set `x109.output_current` from their own measurement.
+    #[deprecated(
+        note = "use `set_charge_current_limit`, or `x208.set_input_current` for discharge; 109.3 is no longer written"
+    )]
     pub fn update_dynamic_charge_limits(&mut self, amps: impl Into<f32>) {
         let amps: f32 = amps.into();
         match amps.is_sign_negative() {
-            true => self.set_max_discharge_amps((-1.0 * amps) as u8),
-            false => self.set_max_charge_amps(amps as u8),
+            true => self.set_max_discharge_amps(-amps as u8),
+            false => self.set_charge_current_limit(amps as u8),
         }
     }
     pub fn output_volts(&self) -> &f32 {
         &self.x109.output_voltage
     }
-    fn set_max_charge_amps(&mut self, amps: impl Into<u8>) {
-        self.x109.output_current = amps.into();
+    /// Limit the charging current, capped at the rated `max_amps`
+    ///
+    /// Always caps the [`evse::Evse`] output. Available output current (108.3) follows before
+    /// charging and, under dynamic control, while charging.
+    pub fn set_charge_current_limit(&mut self, amps: u8) {
+        self.charge_limit = amps.min(self.max_amps);
+        if !self.x109.status.status_station || self.active_protocol().dynamic_current_control() {
+            self.x108.available_output_current = self.charge_limit;
+        }
+    }
+    pub fn charge_current_limit(&self) -> u8 {
+        self.charge_limit
     }
     fn set_max_discharge_amps(&mut self, amps: impl Into<u8>) {
         self.x208.set_input_current(amps.into());
@@ -114,11 +492,36 @@ where
     pub fn requested_charging_amps(&self) -> f32 {
         self.x102.charging_current_request as f32
     }
+    /// Discharge limit from 200.0, reads 0 while [`Self::discharge_limit_masked`]
     pub fn requested_discharging_amps(&self) -> f32 {
-        self.x200.maximum_discharge_current as f32
+        match self.discharge_limit_masked() {
+            true => 0.0,
+            false => self.x200.maximum_discharge_current as f32,
+        }
+    }
+    /// Vehicles built before V2H guideline 1.1 may send a non-zero initial 200.0.
+    ///
+    /// The limit is ignored until the vehicle is discharge compatible (102.5.7),
+    /// permits charging/discharging (102.5.0) and has closed its contactors (102.5.3).
+    /// With [`Quirks::mask_initial_discharge_limit`] also until the vehicle updates it.
+    pub fn discharge_limit_masked(&self) -> bool {
+        let stale = self.quirks.mask_initial_discharge_limit && !self.discharge_limit_updated;
+        stale
+            || !(self.x102.can_discharge()
+                && self.x102.car_ready()
+                && self.x102.contactors_closed())
     }
+    /// 200.7 in %, converted from kWh for vehicles with [`Quirks::capacity_unit`] set
     pub fn max_remaining_capacity_for_charging(&self) -> f32 {
-        self.x200.max_remaining_capacity_for_charging as f32
+        let raw = self.x200.max_remaining_capacity_for_charging;
+        let capacity = self.x101.rated_battery_capacity();
+        self.quirks.capacity_unit.to_percent(raw, capacity) as f32
+    }
+    /// 200.6 in %, converted like [`Self::max_remaining_capacity_for_charging`]
+    pub fn minimum_battery_discharge_level(&self) -> f32 {
+        let raw = self.x200.minimum_battery_discharge_level;
+        let capacity = self.x101.rated_battery_capacity();
+        self.quirks.capacity_unit.to_percent(raw, capacity) as f32
     }
 
     pub fn status_vehicle_contactors(&self) -> bool {
@@ -133,13 +536,26 @@ where
         &self.x102.target_battery_voltage
     }
 
+    /// Set 109.5.0, a new session drops the previous one's fault snapshot
     pub fn charge_start(&mut self) {
-        self.x109.status.status_charger_stop_control = false;
+        self.release_stop_control();
         self.x109.status.status_station = true;
-        self.x109.remaining_charging_time_10s_bit = 255;
-        self.x109.remaining_charging_time_1min_bit = 60;
+        self.charge_started_ms = None;
+        self.history.clear_snapshot();
+        // The vehicle's 101.3 estimate stands in until output power is known
+        let remaining = self
+            .remaining_charging_time()
+            .unwrap_or(self.x101.estimated_charging_time());
+        let remaining = self
+            .x101
+            .max_charging_time()
+            .map_or(remaining, |limit| remaining.min(limit));
+        let in_minutes = self.active_protocol().remaining_time_in_minutes();
+        self.x109.set_remaining_charging_time(remaining, in_minutes);
     }
+    /// Zero the output and clear 109.5.0, ending the charging time count
     pub fn charge_stop(&mut self) {
+        self.x109.status.status_station = false;
         self.x109.output_voltage = 0.0;
         self.x109.output_current = 0;
         self.x109.remaining_charging_time_10s_bit = 0;
@@ -158,39 +574,210 @@ where
         !self.x102.status.status_vehicle
     }
     pub fn charging_stop_control_set(&mut self) {
-        self.x109.status.status_charger_stop_control = false
+        self.release_stop_control();
+    }
+    fn release_stop_control(&mut self) {
+        if self.x109.status.status_charger_stop_control {
+            info!("109.5.5 stop control released");
+        }
+        self.x109.status.status_charger_stop_control = false;
     }
     pub fn charging_stop_control_release(&mut self) {
-        self.x109.status.status_charger_stop_control = true
+        self.request_stop(error::StopReason::User);
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::interface::{raw_to_id, ChademoCanFrame};
-    #[cfg(feature = "eh1")]
-    pub(crate) use embedded_can::Frame;
-    #[cfg(feature = "eh0")]
-    pub(crate) use embedded_hal::can::Frame;
     use frames::X109;
 
     #[test]
     fn soc_test() {
-        let frame = ChademoCanFrame::new(
-            raw_to_id(0x102),
-            [0x2, 0x9A, 0x1, 0x0E, 0x0, 0xC1, 0x56, 0x0].as_slice(),
-        )
-        .unwrap();
+        let data = [0x2, 0x9A, 0x1, 0x0E, 0x0, 0xC1, 0x56, 0x0];
 
         let mut chademo = Chademo::new(15);
-        chademo.x109 = X109::<ChademoCanFrame>::new(2, true);
-        chademo.x102 = X102::from(&frame);
+        chademo.x109 = X109::new(2, true);
+        chademo.x102 = X102::from_payload(&data).unwrap();
         assert_eq!(chademo.soc(), &86)
     }
     #[test]
+    fn discharge_mask_test() {
+        let x200 = [0xFF - 20, 0x0, 0x0, 0x0, 0xFA, 0x0, 0xFF, 0xFF];
+        let mut chademo = Chademo::new(15);
+        chademo.decode_payload(0x200, &x200).unwrap();
+        assert!(chademo.discharge_limit_masked());
+        assert_eq!(chademo.requested_discharging_amps(), 0.0);
+
+        // 102.5.7 + 102.5.0 with contactors open (102.5.3)
+        let x102 = [0x2, 0x9A, 0x1, 0x0E, 0x0, 0x89, 0x56, 0x0];
+        chademo.decode_payload(0x102, &x102).unwrap();
+        assert!(chademo.discharge_limit_masked());
+
+        let x102 = [0x2, 0x9A, 0x1, 0x0E, 0x0, 0x81, 0x56, 0x0];
+        chademo.decode_payload(0x102, &x102).unwrap();
+        assert!(!chademo.discharge_limit_masked());
+        assert_eq!(chademo.requested_discharging_amps(), 20.0);
+    }
+    #[test]
+    fn sequence_test() {
+        // Replay the Leaf session and follow it the way Evse does, 209.0 must match the charger
+        let mut chademo = Chademo::new(16);
+        let log = include_str!("../CanLogs/nissan-leaf-chademo-start-stop.csv");
+        let (mut started, mut stopped, mut checked) = (false, false, 0);
+        for record in trace::records(log) {
+            let record = record.unwrap();
+            let id = record.standard_id().unwrap();
+            if id == X209::ID {
+                let tx = chademo.tx_payloads()[3];
+                assert_eq!(
+                    tx.1[0],
+                    record.data()[0],
+                    "209.0 at {}",
+                    record.timestamp_ms()
+                );
+                checked += 1;
+                continue;
+            }
+            if chademo.decode_payload(id, record.data()).is_err() || id != X102::ID {
+                continue;
+            }
+            let x102 = chademo.x102;
+            if x102.car_ready() {
+                chademo.plug_lock(true);
+            }
+            if x102.contactors_closed() && !started {
+                chademo.charge_start();
+                started = true;
+            }
+            if x102.stop_reason().is_some() && started && !stopped {
+                chademo.request_stop_charge();
+                chademo.charge_stop();
+                stopped = true;
+            }
+        }
+        assert!(checked > 100 && stopped);
+        assert_eq!(chademo.sequence(), 0x02);
+
+        chademo.set_sequence(0x03);
+        assert_eq!(chademo.tx_payloads()[3].1[0], 0x03);
+    }
+    #[test]
+    fn protocol_test() {
+        let x102 = |protocol: u8| [protocol, 0x9A, 0x1, 0x0E, 0x0, 0xC1, 0x56, 0x0];
+        let mut chademo = Chademo::new(15);
+        assert_eq!(chademo.protocol(), None);
+        chademo.decode_payload(0x102, &x102(3)).unwrap();
+        assert_eq!(chademo.protocol(), Some(ProtocolNumber::V1_0));
+        assert_eq!(chademo.x109.control_protocol_number(), 2);
+
+        chademo.decode_payload(0x102, &x102(1)).unwrap();
+        assert_eq!(chademo.protocol(), Some(ProtocolNumber::V0_9));
+        // 101.3 estimate of 10 min until output power is known
+        let x101 = |max_10s: u8| [0x00, max_10s, 0x3C, 0x0A, 0x00, 0xF0, 0x00, 0x00];
+        chademo.decode_payload(0x101, &x101(0xFF)).unwrap();
+        chademo.charge_start();
+        assert_eq!(chademo.x109.remaining_charging_time_10s_bit, 60);
+        assert_eq!(chademo.x109.remaining_charging_time_1min_bit, 0);
+        // capped by the 300 s maximum charging time
+        chademo.decode_payload(0x101, &x101(30)).unwrap();
+        chademo.charge_start();
+        assert_eq!(chademo.x109.remaining_charging_time_10s_bit, 30);
+
+        // Without dynamic control 108.3 is set before charging and then held
+        let mut chademo = Chademo::new(15);
+        chademo.decode_payload(0x102, &x102(2)).unwrap();
+        chademo.set_charge_current_limit(10);
+        assert_eq!(chademo.x108.available_output_current, 10);
+        chademo.charge_start();
+        chademo.set_charge_current_limit(12);
+        assert_eq!(chademo.x108.available_output_current, 10);
+        assert_eq!(chademo.charge_current_limit(), 12);
+        assert_eq!(chademo.x109.output_current, 0);
+
+        let mut chademo = Chademo::new(15);
+        chademo.set_evse_protocol(ProtocolNumber::V1_1);
+        chademo.decode_payload(0x102, &x102(3)).unwrap();
+        chademo.charge_start();
+        chademo.set_charge_current_limit(10);
+        assert_eq!(chademo.x108.available_output_current, 10);
+        chademo.set_charge_current_limit(40);
+        assert_eq!(chademo.x108.available_output_current, 15);
+
+        let mut chademo = Chademo::new(15);
+        assert!(chademo.decode_payload(0x102, &x102(0)).is_err());
+        assert!(chademo.x109_status().fault_battery_incompatibility);
+        assert!(chademo.x109_status().status_charger_stop_control);
+        assert_eq!(
+            chademo.stop_reason(),
+            Some(error::StopReason::IncompatibleProtocol(0))
+        );
+    }
+    #[test]
+    fn max_charging_time_test() {
+        let x101 = [0x00, 0xFF, 0x02, 0x00, 0x00, 0xDE, 0x00, 0x00];
+        let mut chademo = Chademo::new(15);
+        chademo.decode_payload(0x101, &x101).unwrap();
+        chademo.tick(1_000);
+        assert_eq!(chademo.charging_time(1_000), 0);
+        chademo.charge_start();
+        chademo.tick(5_000);
+        chademo.tick(124_999);
+        assert!(!chademo.x109_status().status_charger_stop_control);
+        assert_eq!(chademo.charging_time(124_999), 119_999);
+        chademo.tick(125_000);
+        assert!(chademo.x109_status().status_charger_stop_control);
+        assert_eq!(
+            chademo.stop_reason(),
+            Some(error::StopReason::MaxChargingTime)
+        );
+        // The first cause is kept while stop control stays set
+        chademo.request_stop_charge();
+        assert_eq!(
+            chademo.stop_reason(),
+            Some(error::StopReason::MaxChargingTime)
+        );
+        assert!(chademo
+            .to_string()
+            .ends_with("\nstop: 101.1-2 maximum charging time elapsed"));
+
+        // A second session counts from its own start
+        chademo.charge_stop();
+        chademo.charge_start();
+        chademo.tick(200_000);
+        assert!(!chademo.x109_status().status_charger_stop_control);
+        assert_eq!(chademo.charging_time(200_000), 0);
+        chademo.tick(319_999);
+        assert!(!chademo.x109_status().status_charger_stop_control);
+        chademo.tick(320_000);
+        assert!(chademo.x109_status().status_charger_stop_control);
+    }
+    #[test]
+    fn remaining_charging_time_test() {
+        let x101 = [0x00, 0xFF, 0x3C, 0x00, 0x00, 0xF0, 0x00, 0x00];
+        let x102 = [0x2, 0x9A, 0x1, 0x0E, 0x0, 0xC1, 0x32, 0x0];
+        let mut chademo = Chademo::new(30);
+        chademo.decode_payload(0x101, &x101).unwrap();
+        chademo.decode_payload(0x102, &x102).unwrap();
+        assert_eq!(chademo.remaining_charging_time(), None);
+        chademo.charge_start();
+        chademo.x109.output_voltage = 400.0;
+        chademo.x109.output_current = 30;
+        // 24 kWh * 30 % at 12 kW
+        chademo.set_target_soc(Some(80));
+        assert_eq!(chademo.remaining_charging_time(), Some(36 * 60));
+        chademo.tick(0);
+        assert_eq!(chademo.x109.remaining_charging_time_10s_bit, 0xFF);
+        assert_eq!(chademo.x109.remaining_charging_time_1min_bit, 36);
+
+        // capped by the vehicle maximum charging time
+        chademo.set_target_soc(None);
+        chademo.tick(30 * 60 * 1000);
+        assert_eq!(chademo.x109.remaining_charging_time_1min_bit, 30);
+    }
+    #[test]
     fn x208_test() {
-        let y = X208::<ChademoCanFrame>::new(1, 500, 16, 250);
+        let y = X208::new(1, 500, 16, 250);
         println!(
             "{} {} {} {}",
             y.get_discharge_current(),
@@ -202,11 +789,11 @@ mod test {
         assert!(y.get_input_voltage() == 500);
         assert!(y.get_input_current() == 16);
         assert!(y.get_lower_threshold_voltage() == 250);
-        let cf: ChademoCanFrame = y.to_can().unwrap();
-        assert!(cf.data()[0] == 0xff - 1);
-        assert!(cf.data()[3] == 0xff - 16);
+        let data = y.to_payload();
+        assert!(data[0] == 0xff - 1);
+        assert!(data[3] == 0xff - 16);
 
-        let y = X208::<ChademoCanFrame>::from(&cf);
+        let y = X208::from_payload(&data).unwrap();
         println!(
             "{} {} {} {}",
             y.get_discharge_current(),
@@ -219,4 +806,34 @@ mod test {
         assert!(y.get_input_current() == 16);
         assert!(y.get_lower_threshold_voltage() == 250);
     }
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_test() {
+        let x101 = [0x00, 0xFF, 0x3C, 0x00, 0x00, 0xF0, 0x00, 0x00];
+        let x102 = [0x2, 0x9A, 0x1, 0x0E, 0x0, 0xC1, 0x32, 0x0];
+        let mut chademo = Chademo::new(30);
+        chademo.decode_payload(0x101, &x101).unwrap();
+        chademo.decode_payload(0x102, &x102).unwrap();
+        chademo.request_stop(error::StopReason::NormalStopRequest);
+
+        // Spec names in place of the misspelt fields
+        let json = serde_json::to_string(&chademo.snapshot()).unwrap();
+        assert!(json.contains("\"available_output_voltage\":500"));
+        assert!(json.contains("\"discharge_compatibility\":true"));
+        assert!(json.contains("\"remaining_discharge_time\":0"));
+        assert!(json.contains("\"stop_reason\":\"NormalStopRequest\""));
+
+        // postcard round trip into a stack buffer
+        let mut buf = [0u8; 256];
+        let bytes = postcard::to_slice(&chademo.snapshot(), &mut buf).unwrap();
+        let restored = Chademo::from(postcard::from_bytes::<Snapshot>(bytes).unwrap());
+        assert_eq!(restored.tx_payloads(), chademo.tx_payloads());
+        assert_eq!(restored.x101.to_payload(), x101);
+        assert_eq!(restored.x102.to_payload(), x102);
+        assert_eq!(restored.protocol(), Some(ProtocolNumber::V1_0));
+        assert_eq!(
+            restored.stop_reason(),
+            Some(error::StopReason::NormalStopRequest)
+        );
+    }
 }
@@ -0,0 +1,144 @@
+//! Energy metering and session accounting from caller-supplied timestamps.
+//!
+//! [`SessionMeter`] integrates output voltage and current (positive into the vehicle) between
+//! samples, keeping charged and discharged energy apart, and closes into a [`SessionRecord`].
+use crate::error::StopReason;
+use crate::Chademo;
+
+/// Totals of one session, running or finished
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct SessionRecord {
+    pub started_ms: u64,
+    /// Last sample, or the end of the session once finished
+    pub ended_ms: u64,
+    pub charged_wh: f64,
+    pub discharged_wh: f64,
+    /// Highest power into the vehicle in W
+    pub peak_charge_w: f32,
+    /// Highest power out of the vehicle in W
+    pub peak_discharge_w: f32,
+    pub start_soc: u8,
+    pub end_soc: u8,
+    /// Set once the session is finished
+    pub stop_reason: Option<StopReason>,
+}
+
+impl SessionRecord {
+    pub fn duration_ms(&self) -> u64 {
+        self.ended_ms.saturating_sub(self.started_ms)
+    }
+}
+
+impl core::fmt::Display for SessionRecord {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(
+            f,
+            "{} s: +{:.1} Wh (peak {:.0} W) -{:.1} Wh (peak {:.0} W) SoC {}% -> {}%",
+            self.duration_ms() / 1000,
+            self.charged_wh,
+            self.peak_charge_w,
+            self.discharged_wh,
+            self.peak_discharge_w,
+            self.start_soc,
+            self.end_soc,
+        )?;
+        match self.stop_reason {
+            Some(reason) => write!(f, ", {reason}"),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SessionMeter {
+    record: Option<SessionRecord>,
+    /// Previous `(volts, amps)` sample for trapezoidal integration
+    last: (f32, f32),
+}
+
+impl SessionMeter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a measurement, the first one starts the session
+    pub fn sample(&mut self, now_ms: u64, volts: f32, amps: f32, soc: u8) {
+        let record = self.record.get_or_insert(SessionRecord {
+            started_ms: now_ms,
+            ended_ms: now_ms,
+            start_soc: soc,
+            ..Default::default()
+        });
+        let hours = now_ms.saturating_sub(record.ended_ms) as f64 / 3_600_000.0;
+        let power = volts * amps;
+        let watt_hours = (self.last.0 * self.last.1 + power) as f64 / 2.0 * hours;
+        match watt_hours >= 0.0 {
+            true => record.charged_wh += watt_hours,
+            false => record.discharged_wh -= watt_hours,
+        }
+        record.peak_charge_w = record.peak_charge_w.max(power);
+        record.peak_discharge_w = record.peak_discharge_w.max(-power);
+        record.ended_ms = now_ms;
+        record.end_soc = soc;
+        self.last = (volts, amps);
+    }
+
+    /// Sample the present output (109.1-3, 208.0) and SoC (102.6) of `chademo`
+    pub fn sample_chademo(&mut self, now_ms: u64, chademo: &Chademo) {
+        let amps = chademo.x109.output_current as f32 - chademo.x208.get_discharge_current() as f32;
+        let volts = chademo.x109.output_voltage;
+        self.sample(now_ms, volts, amps, chademo.x102.state_of_charge);
+    }
+
+    /// Totals so far, `None` before the first sample
+    pub fn totals(&self) -> Option<SessionRecord> {
+        self.record
+    }
+
+    /// Close the session at `now_ms` and reset the meter for the next one
+    pub fn finish(&mut self, now_ms: u64, reason: Option<StopReason>) -> Option<SessionRecord> {
+        let mut record = self.record.take()?;
+        record.ended_ms = record.ended_ms.max(now_ms);
+        record.stop_reason = reason;
+        self.last = (0.0, 0.0);
+        Some(record)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn meter_test() {
+        let mut meter = SessionMeter::new();
+        assert_eq!(meter.totals(), None);
+        // 10 minutes at 400 V 30 A, one sample per second
+        for s in 0..=600 {
+            meter.sample(s * 1000, 400.0, 30.0, 50 + (s / 100) as u8);
+        }
+        let totals = meter.totals().unwrap();
+        assert!((totals.charged_wh - 2000.0).abs() < 1e-6);
+        assert_eq!(totals.peak_charge_w, 12_000.0);
+        assert_eq!((totals.start_soc, totals.end_soc), (50, 56));
+
+        // Ramp through zero into 5 minutes of discharge at 20 A
+        meter.sample(601_000, 400.0, -20.0, 56);
+        for s in 602..=900 {
+            meter.sample(s * 1000, 400.0, -20.0, 55);
+        }
+        let record = meter
+            .finish(905_000, Some(StopReason::NormalStopRequest))
+            .unwrap();
+        assert!((record.charged_wh - 2000.0 - 400.0 * 5.0 / 3600.0).abs() < 1e-6);
+        assert!((record.discharged_wh - 400.0 * 20.0 * 299.0 / 3600.0).abs() < 1e-6);
+        assert_eq!(record.peak_discharge_w, 8000.0);
+        assert_eq!(record.duration_ms(), 905_000);
+        assert_eq!(
+            record.to_string(),
+            "905 s: +2000.6 Wh (peak 12000 W) -664.4 Wh (peak 8000 W) SoC 50% -> 55%, 102.5.4 normal stop request"
+        );
+        assert_eq!(meter.totals(), None);
+        assert_eq!(meter.finish(1_000_000, None), None);
+    }
+}
@@ -0,0 +1,155 @@
+//! Passive listener decoding both directions of a CHAdeMO session.
+//!
+//! Unlike [`crate::Chademo`], which only decodes vehicle frames, [`ChademoMonitor`] accepts all
+//! eight IDs and never transmits, for sniffing third-party chargers or replaying `CanLogs/`.
+use crate::error::ChademoError;
+use crate::frames::*;
+
+/// Session phase derived from the observed 102.5 and 109.5 flags
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Phase {
+    /// No frames seen
+    #[default]
+    Idle,
+    /// Parameter exchange, connector not yet locked
+    Initialisation,
+    /// 109.5.2 set, insulation test and vehicle contactors, 109.5.0 not yet set
+    Preparation,
+    /// 109.5.0 set and neither side requesting a stop
+    Charging,
+    /// 109.5.5 set, 102.5.0 cleared or output ceased with the connector still locked
+    Stopping,
+    /// Connector unlocked after charging
+    Finished,
+}
+
+impl core::fmt::Display for Phase {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        core::fmt::Debug::fmt(self, f)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ChademoMonitor {
+    /// Vehicle view
+    pub x100: X100,
+    pub x101: X101,
+    pub x102: X102,
+    pub x200: X200,
+    /// Charger view
+    pub x108: X108,
+    pub x109: X109,
+    pub x208: X208,
+    pub x209: X209,
+    vehicle_seen: bool,
+    evse_seen: bool,
+    phase: Phase,
+}
+
+impl ChademoMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decode a raw frame from either side and return the resulting phase
+    pub fn decode_payload(&mut self, id: u16, data: &[u8]) -> Result<Phase, ChademoError> {
+        match id {
+            X100::ID => self.x100 = X100::from_payload(data)?,
+            X101::ID => self.x101 = X101::from_payload(data)?,
+            X102::ID => self.x102 = X102::from_payload(data)?,
+            X200::ID => self.x200 = X200::from_payload(data)?,
+            X108::ID => self.x108 = X108::from_payload(data)?,
+            X109::ID => self.x109 = X109::from_payload(data)?,
+            X208::ID => self.x208 = X208::from_payload(data)?,
+            X209::ID => self.x209 = X209::from_payload(data)?,
+            bad_id => return Err(ChademoError::DecodeBadId(bad_id)),
+        };
+        match id {
+            X100::ID | X101::ID | X102::ID | X200::ID => self.vehicle_seen = true,
+            _ => self.evse_seen = true,
+        }
+        self.phase = self.next_phase();
+        Ok(self.phase)
+    }
+
+    fn next_phase(&self) -> Phase {
+        let ev = self.x102;
+        let evse = self.x109.status;
+        let started = self.phase >= Phase::Charging;
+        match () {
+            _ if !self.vehicle_seen && !self.evse_seen => Phase::Idle,
+            _ if evse.status_station && (evse.status_charger_stop_control || ev.stop()) => {
+                Phase::Stopping
+            }
+            _ if evse.status_station => Phase::Charging,
+            _ if started && evse.status_vehicle_connector_lock => Phase::Stopping,
+            _ if started => Phase::Finished,
+            _ if evse.status_vehicle_connector_lock => Phase::Preparation,
+            _ => Phase::Initialisation,
+        }
+    }
+
+    pub fn phase(&self) -> Phase {
+        self.phase
+    }
+
+    /// True once frames from both the vehicle and the charger have been seen
+    pub fn both_sides_seen(&self) -> bool {
+        self.vehicle_seen && self.evse_seen
+    }
+
+    /// Protocol both sides agree on from 102.0 and 109.0
+    pub fn protocol(&self) -> Option<ProtocolNumber> {
+        let evse = ProtocolNumber::try_from(self.x109.control_protocol_number()).ok()?;
+        ProtocolNumber::negotiate(self.x102.control_protocol_number_ev, evse).ok()
+    }
+}
+
+impl core::fmt::Display for ChademoMonitor {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(
+            f,
+            "{} SoC {}% req {}A/{}V out {}A/{}V\nx102: status {}\nx109: status {}",
+            self.phase,
+            self.x102.state_of_charge,
+            self.x102.charging_current_request,
+            self.x102.target_battery_voltage,
+            self.x109.output_current,
+            self.x109.output_voltage,
+            self.x102.status,
+            self.x109.status,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::trace;
+
+    #[test]
+    fn capture_phase_test() {
+        let log = include_str!("../CanLogs/nissan-leaf-chademo-start-stop.csv");
+        let mut monitor = ChademoMonitor::new();
+        assert_eq!(monitor.phase(), Phase::Idle);
+
+        let mut phases = std::vec::Vec::new();
+        for record in trace::records(log) {
+            let record = record.unwrap();
+            let phase = monitor
+                .decode_payload(record.standard_id().unwrap(), record.data())
+                .unwrap();
+            if phases.last() != Some(&phase) {
+                phases.push(phase);
+            }
+        }
+        use Phase::*;
+        assert_eq!(
+            phases,
+            [Initialisation, Preparation, Charging, Stopping, Finished]
+        );
+        assert!(monitor.both_sides_seen());
+        assert_eq!(monitor.protocol(), Some(ProtocolNumber::V1_0));
+        assert!(monitor.decode_payload(0x110, &[0; 8]).is_err());
+    }
+}
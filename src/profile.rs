@@ -0,0 +1,293 @@
+//! Per-model vehicle quirks, picked by fingerprinting H'100/H'101/H'102.
+//!
+//! [`ProfileRegistry`] checks user registered profiles before [`ProfileRegistry::BUILTIN`],
+//! [`crate::evse::Evse`] applies the match once the vehicle permits charging (102.5.0).
+//! The built-in fingerprints are approximate, register a profile for a car they miss.
+use crate::frames::{ProtocolNumber, X100, X101, X102};
+
+/// Unit of the vehicle's 200.6 and 200.7 capacity limits
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CapacityUnit {
+    /// V2H guideline 1.0 onwards
+    #[default]
+    Percent,
+    /// Vehicles before V2H guideline 1.0, converted with the rated capacity (101.5-6)
+    Kwh,
+    /// As [`CapacityUnit::Kwh`] in 0.1 kWh, seen from the Leaf ZE0 in `CanLogs/`
+    DeciKwh,
+}
+
+impl CapacityUnit {
+    /// 200.6/200.7 raw value in %, decimals cut off as the spec asks
+    pub fn to_percent(self, raw: u8, rated_capacity_kwh: f32) -> u8 {
+        let kwh = match self {
+            CapacityUnit::Percent => return raw,
+            CapacityUnit::Kwh => raw as f32,
+            CapacityUnit::DeciKwh => raw as f32 / 10.0,
+        };
+        match rated_capacity_kwh > 0.0 {
+            true => (kwh / rated_capacity_kwh * 100.0).min(100.0) as u8,
+            false => 0,
+        }
+    }
+}
+
+/// Workarounds for a vehicle deviating from the spec
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Quirks {
+    /// Initial 200.0 is not 0 (before V2H guideline 1.1), masked until the vehicle updates it
+    pub mask_initial_discharge_limit: bool,
+    pub capacity_unit: CapacityUnit,
+    /// Highest protocol to agree on whatever 102.0 claims
+    pub max_protocol: Option<ProtocolNumber>,
+    /// H'102 timeout in place of [`crate::evse::Evse::RX_TIMEOUT_MS`]
+    pub rx_timeout_ms: Option<u64>,
+}
+
+/// Vehicle parameters a profile matches on, `None` matches anything
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Fingerprint {
+    /// 102.0
+    pub protocol: Option<u8>,
+    /// 100.4-5 inclusive range in V
+    pub maximum_battery_voltage: Option<(f32, f32)>,
+    /// 101.5-6 inclusive range in kWh, a capacity of 0 (not reported) never matches
+    pub rated_battery_capacity: Option<(f32, f32)>,
+}
+
+impl Fingerprint {
+    pub fn matches(&self, x100: &X100, x101: &X101, x102: &X102) -> bool {
+        let within = |range: Option<(f32, f32)>, value: f32| {
+            range.is_none_or(|(min, max)| (min..=max).contains(&value))
+        };
+        let capacity = x101.rated_battery_capacity();
+        self.protocol
+            .is_none_or(|protocol| protocol == x102.control_protocol_number_ev)
+            && within(self.maximum_battery_voltage, x100.maximum_battery_voltage)
+            && (self.rated_battery_capacity.is_none() || capacity > 0.0)
+            && within(self.rated_battery_capacity, capacity)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VehicleProfile {
+    pub name: &'static str,
+    pub fingerprint: Fingerprint,
+    pub quirks: Quirks,
+}
+
+/// Tesla CHAdeMO adapter, opt in through [`ProfileRegistry::register`]
+///
+/// Not built in: the fingerprint has no capture behind it and the quirks relax the 1 s H'102
+/// timeout to 2 s.
+pub const TESLA_ADAPTER: VehicleProfile = VehicleProfile {
+    name: "Tesla CHAdeMO adapter",
+    fingerprint: Fingerprint {
+        protocol: Some(2),
+        maximum_battery_voltage: Some((400.0, 420.0)),
+        rated_battery_capacity: None,
+    },
+    quirks: Quirks {
+        mask_initial_discharge_limit: false,
+        capacity_unit: CapacityUnit::Percent,
+        max_protocol: Some(ProtocolNumber::V1_0),
+        rx_timeout_ms: Some(2_000),
+    },
+};
+
+/// Built-in profiles plus up to `N` registered by the user
+#[derive(Debug, Clone)]
+pub struct ProfileRegistry<const N: usize = 8> {
+    custom: [Option<VehicleProfile>; N],
+}
+
+impl<const N: usize> Default for ProfileRegistry<N> {
+    fn default() -> Self {
+        Self { custom: [None; N] }
+    }
+}
+
+impl<const N: usize> ProfileRegistry<N> {
+    /// Most specific first, the first match wins
+    pub const BUILTIN: &'static [VehicleProfile] = &[
+        VehicleProfile {
+            name: "Nissan Leaf ZE0",
+            fingerprint: Fingerprint {
+                protocol: Some(2),
+                maximum_battery_voltage: Some((430.0, 440.0)),
+                rated_battery_capacity: Some((20.0, 25.0)),
+            },
+            quirks: Quirks {
+                mask_initial_discharge_limit: false,
+                capacity_unit: CapacityUnit::DeciKwh,
+                max_protocol: None,
+                rx_timeout_ms: None,
+            },
+        },
+        VehicleProfile {
+            name: "Nissan Leaf ZE1",
+            fingerprint: Fingerprint {
+                protocol: Some(3),
+                maximum_battery_voltage: Some((430.0, 440.0)),
+                rated_battery_capacity: Some((36.0, 65.0)),
+            },
+            quirks: Quirks {
+                mask_initial_discharge_limit: false,
+                capacity_unit: CapacityUnit::Percent,
+                max_protocol: None,
+                rx_timeout_ms: None,
+            },
+        },
+        VehicleProfile {
+            name: "Nissan e-NV200",
+            fingerprint: Fingerprint {
+                protocol: Some(2),
+                maximum_battery_voltage: Some((430.0, 440.0)),
+                rated_battery_capacity: Some((25.0, 42.0)),
+            },
+            quirks: Quirks {
+                mask_initial_discharge_limit: false,
+                capacity_unit: CapacityUnit::DeciKwh,
+                max_protocol: None,
+                rx_timeout_ms: None,
+            },
+        },
+        VehicleProfile {
+            name: "Mitsubishi Outlander PHEV",
+            fingerprint: Fingerprint {
+                protocol: None,
+                maximum_battery_voltage: Some((290.0, 350.0)),
+                rated_battery_capacity: Some((10.0, 14.0)),
+            },
+            quirks: Quirks {
+                mask_initial_discharge_limit: true,
+                capacity_unit: CapacityUnit::Kwh,
+                max_protocol: None,
+                rx_timeout_ms: None,
+            },
+        },
+        VehicleProfile {
+            name: "Mitsubishi i-MiEV",
+            fingerprint: Fingerprint {
+                protocol: Some(1),
+                maximum_battery_voltage: Some((350.0, 380.0)),
+                rated_battery_capacity: None,
+            },
+            quirks: Quirks {
+                mask_initial_discharge_limit: true,
+                capacity_unit: CapacityUnit::Kwh,
+                max_protocol: Some(ProtocolNumber::V0_9),
+                rx_timeout_ms: None,
+            },
+        },
+    ];
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a profile checked before the built-in ones, handed back when the registry is full
+    pub fn register(&mut self, profile: VehicleProfile) -> Result<(), VehicleProfile> {
+        match self.custom.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => {
+                *slot = Some(profile);
+                Ok(())
+            }
+            None => Err(profile),
+        }
+    }
+
+    /// First registered, then built-in profile matching the vehicle
+    pub fn identify(&self, x100: &X100, x101: &X101, x102: &X102) -> Option<&VehicleProfile> {
+        self.custom
+            .iter()
+            .flatten()
+            .chain(Self::BUILTIN)
+            .find(|profile| profile.fingerprint.matches(x100, x101, x102))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::frames::Payload;
+    use crate::{trace, Chademo};
+
+    #[test]
+    fn identify_test() {
+        // Leaf ZE0 from the start of the capture
+        let mut chademo = Chademo::new(16);
+        let log = include_str!("../CanLogs/nissan-leaf-chademo-start-stop.csv");
+        for record in trace::records(log).take(200) {
+            let record = record.unwrap();
+            let id = record.standard_id().unwrap();
+            let _ = chademo.decode_payload(id, record.data());
+        }
+        let registry: ProfileRegistry = ProfileRegistry::new();
+        assert_eq!(chademo.identify(&registry), Some("Nissan Leaf ZE0"));
+        // 200.7 = 17.8 kWh of the 22.2 kWh pack
+        assert_eq!(chademo.x200.max_remaining_capacity_for_charging, 178);
+        assert_eq!(chademo.max_remaining_capacity_for_charging(), 80.0);
+        assert_eq!(chademo.target_soc(), 80);
+
+        // Registered profiles win over the built-in ones
+        let mut registry: ProfileRegistry<1> = ProfileRegistry::new();
+        let custom = VehicleProfile {
+            name: "Bench emulator",
+            fingerprint: Fingerprint {
+                protocol: Some(2),
+                ..Default::default()
+            },
+            quirks: Quirks::default(),
+        };
+        registry.register(custom).unwrap();
+        assert_eq!(registry.register(custom), Err(custom));
+        assert_eq!(chademo.identify(&registry), Some("Bench emulator"));
+        assert_eq!(chademo.max_remaining_capacity_for_charging(), 178.0);
+
+        let x102 = X102::from_payload(&[0x0, 0x9A, 0x1, 0x0E, 0x0, 0xC1, 0x56, 0x0]).unwrap();
+        let registry: ProfileRegistry = ProfileRegistry::new();
+        assert!(registry
+            .identify(&chademo.x100, &chademo.x101, &x102)
+            .is_none());
+
+        // H'101 not yet received reads as 0 kWh, no capacity range matches it
+        let x100 = X100::from_payload(&[0x0, 0x0, 0x0, 0x0, 0xB3, 0x01, 0xF0, 0x0]).unwrap();
+        let profile = registry.identify(&x100, &X101::default(), &chademo.x102);
+        assert!(profile.is_none());
+        // The Tesla adapter and its longer H'102 timeout only apply once registered
+        let x100 = X100::from_payload(&[0x0, 0x0, 0x0, 0x0, 0x9A, 0x01, 0xF0, 0x0]).unwrap();
+        let profile = registry.identify(&x100, &X101::default(), &chademo.x102);
+        assert!(profile.is_none());
+        let mut registry: ProfileRegistry<1> = ProfileRegistry::new();
+        registry.register(TESLA_ADAPTER).unwrap();
+        let profile = registry.identify(&x100, &X101::default(), &chademo.x102);
+        assert_eq!(profile.unwrap().quirks.rx_timeout_ms, Some(2_000));
+    }
+
+    #[test]
+    fn quirks_test() {
+        let mut chademo = Chademo::new(16);
+        chademo.set_quirks(Quirks {
+            mask_initial_discharge_limit: true,
+            max_protocol: Some(ProtocolNumber::V0_9),
+            ..Default::default()
+        });
+        // Initial 200.0 of 20 A stays masked with the contactors closed until it is updated
+        let x102 = [0x2, 0x9A, 0x1, 0x0E, 0x0, 0x81, 0x56, 0x0];
+        chademo.decode_payload(0x102, &x102).unwrap();
+        assert_eq!(chademo.protocol(), Some(ProtocolNumber::V0_9));
+        let x200 = |amps: u8| [0xFF - amps, 0x0, 0x0, 0x0, 0xFA, 0x0, 0xFF, 0xFF];
+        chademo.decode_payload(0x200, &x200(20)).unwrap();
+        chademo.decode_payload(0x200, &x200(20)).unwrap();
+        assert!(chademo.discharge_limit_masked());
+        chademo.decode_payload(0x200, &x200(10)).unwrap();
+        assert_eq!(chademo.requested_discharging_amps(), 10.0);
+
+        assert_eq!(CapacityUnit::Kwh.to_percent(12, 16.0), 75);
+        assert_eq!(CapacityUnit::DeciKwh.to_percent(67, 22.2), 30);
+        assert_eq!(CapacityUnit::Kwh.to_percent(12, 0.0), 0);
+    }
+}
@@ -0,0 +1,219 @@
+//! Man-in-the-middle between a vehicle and a charger on two CAN buses.
+//!
+//! Every CHAdeMO frame is decoded, handed to the [`Rewrite`] rules and re-encoded with the frame
+//! codecs, so reserved bits survive. Frames a rule changed are reported as [`Forward::Rewritten`]
+//! carrying the before/after payloads for logging.
+use crate::error::ChademoError;
+use crate::frames::*;
+
+/// A decoded frame on its way through the proxy
+#[derive(Debug, Clone, Copy)]
+pub enum Decoded {
+    X100(X100),
+    X101(X101),
+    X102(X102),
+    X200(X200),
+    X108(X108),
+    X109(X109),
+    X208(X208),
+    X209(X209),
+}
+
+impl Decoded {
+    pub fn from_payload(id: u16, data: &[u8]) -> Result<Self, ChademoError> {
+        Ok(match id {
+            X100::ID => Self::X100(X100::from_payload(data)?),
+            X101::ID => Self::X101(X101::from_payload(data)?),
+            X102::ID => Self::X102(X102::from_payload(data)?),
+            X200::ID => Self::X200(X200::from_payload(data)?),
+            X108::ID => Self::X108(X108::from_payload(data)?),
+            X109::ID => Self::X109(X109::from_payload(data)?),
+            X208::ID => Self::X208(X208::from_payload(data)?),
+            X209::ID => Self::X209(X209::from_payload(data)?),
+            bad_id => return Err(ChademoError::DecodeBadId(bad_id)),
+        })
+    }
+
+    pub fn id(&self) -> u16 {
+        match self {
+            Self::X100(_) => X100::ID,
+            Self::X101(_) => X101::ID,
+            Self::X102(_) => X102::ID,
+            Self::X200(_) => X200::ID,
+            Self::X108(_) => X108::ID,
+            Self::X109(_) => X109::ID,
+            Self::X208(_) => X208::ID,
+            Self::X209(_) => X209::ID,
+        }
+    }
+
+    pub fn to_payload(&self) -> [u8; 8] {
+        match self {
+            Self::X100(frame) => frame.to_payload(),
+            Self::X101(frame) => frame.to_payload(),
+            Self::X102(frame) => frame.to_payload(),
+            Self::X200(frame) => frame.to_payload(),
+            Self::X108(frame) => frame.to_payload(),
+            Self::X109(frame) => frame.to_payload(),
+            Self::X208(frame) => frame.to_payload(),
+            Self::X209(frame) => frame.to_payload(),
+        }
+    }
+
+    /// Sent by the vehicle (H'100/101/102/200), otherwise by the charger
+    pub fn from_vehicle(&self) -> bool {
+        matches!(
+            self,
+            Self::X100(_) | Self::X101(_) | Self::X102(_) | Self::X200(_)
+        )
+    }
+}
+
+/// User rule applied to every decoded frame, closures `FnMut(&mut Decoded)` are rules too
+pub trait Rewrite {
+    fn rewrite(&mut self, frame: &mut Decoded);
+}
+
+impl<F: FnMut(&mut Decoded)> Rewrite for F {
+    fn rewrite(&mut self, frame: &mut Decoded) {
+        self(frame)
+    }
+}
+
+/// Rules applied in order
+impl<A: Rewrite, B: Rewrite> Rewrite for (A, B) {
+    fn rewrite(&mut self, frame: &mut Decoded) {
+        self.0.rewrite(frame);
+        self.1.rewrite(frame);
+    }
+}
+
+/// Cap the vehicle charging current request (102.3)
+#[derive(Debug, Clone, Copy)]
+pub struct ClampChargingCurrent(pub u8);
+
+impl Rewrite for ClampChargingCurrent {
+    fn rewrite(&mut self, frame: &mut Decoded) {
+        if let Decoded::X102(x102) = frame {
+            x102.charging_current_request = x102.charging_current_request.min(self.0);
+        }
+    }
+}
+
+/// Replace the vehicle charge limit (200.7) in %
+#[derive(Debug, Clone, Copy)]
+pub struct OverrideChargeLimit(pub u8);
+
+impl Rewrite for OverrideChargeLimit {
+    fn rewrite(&mut self, frame: &mut Decoded) {
+        if let Decoded::X200(x200) = frame {
+            x200.max_remaining_capacity_for_charging = self.0;
+        }
+    }
+}
+
+/// Log entry for a frame a rule changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rewritten {
+    pub id: u16,
+    pub before: [u8; 8],
+    pub after: [u8; 8],
+}
+
+impl core::fmt::Display for Rewritten {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(
+            f,
+            "{:03X}: {:02X?} -> {:02X?}",
+            self.id, self.before, self.after
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Forward {
+    /// Send the received frame on untouched, also used for non CHAdeMO IDs
+    Unchanged,
+    /// Send `after` instead
+    Rewritten(Rewritten),
+}
+
+#[derive(Debug, Clone)]
+pub struct ChademoProxy<R> {
+    pub rules: R,
+    rewrites: usize,
+}
+
+impl<R: Rewrite> ChademoProxy<R> {
+    pub fn new(rules: R) -> Self {
+        Self { rules, rewrites: 0 }
+    }
+
+    /// Frames changed so far
+    pub fn rewrites(&self) -> usize {
+        self.rewrites
+    }
+
+    /// Run a frame received on either bus through the rules
+    pub fn forward(&mut self, id: u16, data: &[u8]) -> Result<Forward, ChademoError> {
+        let mut frame = match Decoded::from_payload(id, data) {
+            Ok(frame) => frame,
+            Err(ChademoError::DecodeBadId(_)) => return Ok(Forward::Unchanged),
+            Err(e) => return Err(e),
+        };
+        let before = frame.to_payload();
+        self.rules.rewrite(&mut frame);
+        let after = frame.to_payload();
+        if before == after {
+            return Ok(Forward::Unchanged);
+        }
+        self.rewrites += 1;
+        Ok(Forward::Rewritten(Rewritten { id, before, after }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rewrite_test() {
+        let mut proxy = ChademoProxy::new((ClampChargingCurrent(10), OverrideChargeLimit(80)));
+
+        let x102 = [0x2, 0x9A, 0x1, 0x0E, 0x0, 0xC1, 0x56, 0x5A];
+        let Forward::Rewritten(log) = proxy.forward(0x102, &x102).unwrap() else {
+            panic!("102.3 not clamped");
+        };
+        // Only 102.3 changes, reserved byte 102.7 survives re-encoding
+        assert_eq!(log.after, [0x2, 0x9A, 0x1, 0x0A, 0x0, 0xC1, 0x56, 0x5A]);
+        assert_eq!(
+            log.to_string(),
+            "102: [02, 9A, 01, 0E, 00, C1, 56, 5A] -> [02, 9A, 01, 0A, 00, C1, 56, 5A]"
+        );
+
+        let x200 = [0xFF, 0x1, 0x2, 0x3, 0xFA, 0x0, 0xFF, 0x64];
+        let Forward::Rewritten(log) = proxy.forward(0x200, &x200).unwrap() else {
+            panic!("200.7 not overridden");
+        };
+        assert_eq!(log.after, [0xFF, 0x1, 0x2, 0x3, 0xFA, 0x0, 0xFF, 80]);
+
+        // Already within the clamp, EVSE frames and foreign traffic pass through
+        let x102 = [0x2, 0x9A, 0x1, 0x05, 0x0, 0xC1, 0x56, 0x0];
+        assert_eq!(proxy.forward(0x102, &x102).unwrap(), Forward::Unchanged);
+        let x109 = [0x2, 0x76, 0x1, 0x0E, 0x1, 0x5, 0xFF, 0x3C];
+        assert_eq!(proxy.forward(0x109, &x109).unwrap(), Forward::Unchanged);
+        assert_eq!(proxy.forward(0x5BC, &[0; 3]).unwrap(), Forward::Unchanged);
+        assert!(proxy.forward(0x102, &[0; 3]).is_err());
+        assert_eq!(proxy.rewrites(), 2);
+
+        let mut proxy = ChademoProxy::new(|frame: &mut Decoded| {
+            if let Decoded::X102(x102) = frame {
+                x102.state_of_charge = 50;
+            }
+        });
+        let Forward::Rewritten(log) = proxy.forward(0x102, &x102).unwrap() else {
+            panic!("closure rule not applied");
+        };
+        assert_eq!(log.after[6], 50);
+    }
+}
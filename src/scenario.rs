@@ -0,0 +1,497 @@
+//! TOML scenario files for scripted [`crate::sim`] sessions.
+//!
+//! A scenario sets the vehicle parameters, scripts time-based changes to the H'102/H'200 fields
+//! the vehicle sends and lists what the charger is expected to do. [`Scenario::run`] plays it
+//! against [`crate::evse::Evse`] on a [`SimBus`] and reports pass/fail per expectation.
+//!
+//! ```toml
+//! name = "vehicle clears 102.5.0 mid-charge"
+//!
+//! [vehicle]
+//! soc = 50.0
+//!
+//! [[step]]
+//! at_ms = 5000
+//! x102 = { status_vehicle_charging = false }
+//!
+//! [[expect]]
+//! by_ms = 5200
+//! state = "Stopping"
+//! status_charger_stop_control = true
+//! ```
+use std::format;
+use std::string::String;
+use std::vec::Vec;
+
+use serde::{Deserialize, Deserializer};
+
+use crate::emulator::{VehicleEmulator, VehicleParams};
+use crate::evse::Evse;
+use crate::fault::{Fault, FaultInjector};
+use crate::frames::ProtocolNumber;
+use crate::proxy::{Decoded, Rewrite};
+use crate::sim::{EvseNode, Node, NodeId, SimBus, SimFrame, VehicleNode};
+use crate::Chademo;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    pub name: String,
+    /// Simulated time to run, at least until the last step and expectation
+    #[serde(default)]
+    pub duration_ms: u64,
+    #[serde(default)]
+    pub vehicle: VehicleSpec,
+    #[serde(default)]
+    pub evse: EvseSpec,
+    #[serde(default, rename = "step")]
+    pub steps: Vec<Step>,
+    #[serde(default, rename = "expect")]
+    pub expectations: Vec<Expect>,
+}
+
+/// Vehicle parameters, unset values keep the [`VehicleParams`] defaults
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VehicleSpec {
+    pub soc: Option<f32>,
+    /// 102.0
+    pub protocol: Option<u8>,
+    /// 100.2-3
+    pub minimum_battery_voltage: Option<f32>,
+    /// 100.4-5
+    pub maximum_battery_voltage: Option<f32>,
+    /// 102.1-2
+    pub target_battery_voltage: Option<f32>,
+    /// 102.3 once charging
+    pub max_charging_current: Option<u8>,
+    /// 101.1-2 in seconds
+    pub max_charging_time: Option<u32>,
+    /// 101.5-6 in kWh
+    pub rated_battery_capacity: Option<f32>,
+    /// 102.5.7
+    pub discharge_compatible: Option<bool>,
+    /// 200.0
+    pub max_discharge_current: Option<u8>,
+    /// 200.6
+    pub min_discharge_soc: Option<u8>,
+    pub target_soc: Option<u8>,
+}
+
+impl VehicleSpec {
+    pub fn params(&self) -> VehicleParams {
+        let mut params = VehicleParams::default();
+        let VehicleSpec {
+            soc: _,
+            protocol,
+            minimum_battery_voltage,
+            maximum_battery_voltage,
+            target_battery_voltage,
+            max_charging_current,
+            max_charging_time,
+            rated_battery_capacity,
+            discharge_compatible,
+            max_discharge_current,
+            min_discharge_soc,
+            target_soc,
+        } = self.clone();
+        params.protocol = protocol.unwrap_or(params.protocol);
+        params.minimum_battery_voltage =
+            minimum_battery_voltage.unwrap_or(params.minimum_battery_voltage);
+        params.maximum_battery_voltage =
+            maximum_battery_voltage.unwrap_or(params.maximum_battery_voltage);
+        params.target_battery_voltage =
+            target_battery_voltage.unwrap_or(params.target_battery_voltage);
+        params.max_charging_current = max_charging_current.unwrap_or(params.max_charging_current);
+        params.max_charging_time = max_charging_time.unwrap_or(params.max_charging_time);
+        params.rated_battery_capacity =
+            rated_battery_capacity.unwrap_or(params.rated_battery_capacity);
+        params.discharge_compatible = discharge_compatible.unwrap_or(params.discharge_compatible);
+        params.max_discharge_current =
+            max_discharge_current.unwrap_or(params.max_discharge_current);
+        params.min_discharge_soc = min_discharge_soc.unwrap_or(params.min_discharge_soc);
+        params.target_soc = target_soc.unwrap_or(params.target_soc);
+        params
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct EvseSpec {
+    pub max_amps: u8,
+    /// Highest protocol offered in 109.0, numbers without a [`ProtocolNumber`] are rejected
+    #[serde(deserialize_with = "protocol_number")]
+    pub protocol: ProtocolNumber,
+    /// V2H discharge current, see [`Evse::set_discharge_request`]
+    pub discharge_request: f32,
+}
+
+impl Default for EvseSpec {
+    fn default() -> Self {
+        Self {
+            max_amps: 16,
+            protocol: ProtocolNumber::V1_0,
+            discharge_request: 0.0,
+        }
+    }
+}
+
+fn protocol_number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<ProtocolNumber, D::Error> {
+    ProtocolNumber::try_from(u8::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+}
+
+/// Fields of H'102 to override, names follow [`crate::frames::X102`] and its status
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct X102Override {
+    pub target_battery_voltage: Option<f32>,
+    pub charging_current_request: Option<u8>,
+    pub state_of_charge: Option<u8>,
+    pub status_discharge_compatible: Option<bool>,
+    pub status_normal_stop_request: Option<bool>,
+    pub status_vehicle: Option<bool>,
+    pub status_charging_system: Option<bool>,
+    pub status_vehicle_shifter_position: Option<bool>,
+    pub status_vehicle_charging: Option<bool>,
+}
+
+/// Fields of H'200 to override, names follow [`crate::frames::X200`]
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct X200Override {
+    pub maximum_discharge_current: Option<u8>,
+    pub minimum_discharge_voltage: Option<u16>,
+    pub minimum_battery_discharge_level: Option<u8>,
+    pub max_remaining_capacity_for_charging: Option<u8>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "snake_case")]
+pub enum FaultSpec {
+    /// The vehicle sends nothing
+    Silence,
+    /// Frames with `id` sent with `len` data bytes
+    Dlc { id: u16, len: usize },
+}
+
+/// Change applied from `at_ms` until `until_ms`, or the end of the scenario
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Step {
+    pub at_ms: u64,
+    pub until_ms: Option<u64>,
+    #[serde(default)]
+    pub x102: X102Override,
+    #[serde(default)]
+    pub x200: X200Override,
+    pub fault: Option<FaultSpec>,
+    /// Stop requested at the charger, see [`Evse::stop`]
+    #[serde(default)]
+    pub evse_stop: bool,
+}
+
+impl Step {
+    fn active(&self, now_ms: u64) -> bool {
+        now_ms >= self.at_ms && self.until_ms.is_none_or(|until| now_ms < until)
+    }
+}
+
+impl Rewrite for Step {
+    fn rewrite(&mut self, frame: &mut Decoded) {
+        macro_rules! apply {
+            ($over:expr => $($field:ident).+) => {
+                if let Some(value) = $over {
+                    $($field).+ = value;
+                }
+            };
+        }
+        match frame {
+            Decoded::X102(x102) => {
+                let o = self.x102;
+                apply!(o.target_battery_voltage => x102.target_battery_voltage);
+                apply!(o.charging_current_request => x102.charging_current_request);
+                apply!(o.state_of_charge => x102.state_of_charge);
+                apply!(o.status_discharge_compatible => x102.status.status_discharge_compatible);
+                apply!(o.status_normal_stop_request => x102.status.status_normal_stop_request);
+                apply!(o.status_vehicle => x102.status.status_vehicle);
+                apply!(o.status_charging_system => x102.status.status_charging_system);
+                apply!(o.status_vehicle_shifter_position => x102.status.status_vehicle_shifter_position);
+                apply!(o.status_vehicle_charging => x102.status.status_vehicle_charging);
+            }
+            Decoded::X200(x200) => {
+                let o = self.x200;
+                apply!(o.maximum_discharge_current => x200.maximum_discharge_current);
+                apply!(o.minimum_discharge_voltage => x200.minimum_discharge_voltage);
+                apply!(o.minimum_battery_discharge_level => x200.minimum_battery_discharge_level);
+                apply!(o.max_remaining_capacity_for_charging => x200.max_remaining_capacity_for_charging);
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Charger reaction checked exactly at `at_ms`, or at any time up to `by_ms`
+///
+/// Only the fields given are compared. Flags follow [`crate::frames::X109Status`].
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Expect {
+    pub at_ms: Option<u64>,
+    pub by_ms: Option<u64>,
+    /// [`crate::evse::EvseState`] name, e.g. `"Stopping"`
+    pub state: Option<String>,
+    pub status_station: Option<bool>,
+    pub status_charger_stop_control: Option<bool>,
+    pub status_vehicle_connector_lock: Option<bool>,
+    pub fault_battery_incompatibility: Option<bool>,
+    /// Power stage output current, negative while discharging
+    pub output_current_min: Option<f32>,
+    pub output_current_max: Option<f32>,
+}
+
+impl Expect {
+    fn deadline(&self) -> u64 {
+        self.at_ms.or(self.by_ms).unwrap_or_default()
+    }
+
+    /// `None` when met, otherwise the first mismatch
+    fn check(&self, node: &EvseNode) -> Option<String> {
+        let status = node.evse.chademo.x109_status();
+        let state = format!("{:?}", node.state());
+        let current = node.output.1;
+        if let Some(expected) = &self.state {
+            if *expected != state {
+                return Some(format!("state {state}, expected {expected}"));
+            }
+        }
+        let flags = [
+            ("109.5.0", self.status_station, status.status_station),
+            (
+                "109.5.5",
+                self.status_charger_stop_control,
+                status.status_charger_stop_control,
+            ),
+            (
+                "109.5.2",
+                self.status_vehicle_connector_lock,
+                status.status_vehicle_connector_lock,
+            ),
+            (
+                "109.5.3",
+                self.fault_battery_incompatibility,
+                status.fault_battery_incompatibility,
+            ),
+        ];
+        for (clause, expected, actual) in flags {
+            if expected.is_some_and(|expected| expected != actual) {
+                return Some(format!("{clause} is {}", actual as u8));
+            }
+        }
+        if self.output_current_min.is_some_and(|min| current < min) {
+            return Some(format!("output {current} A below minimum"));
+        }
+        if self.output_current_max.is_some_and(|max| current > max) {
+            return Some(format!("output {current} A above maximum"));
+        }
+        None
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outcome {
+    /// Index into [`Scenario::expectations`]
+    pub index: usize,
+    pub timestamp_ms: u64,
+    /// `None` when the expectation was met
+    pub failure: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub name: String,
+    pub outcomes: Vec<Outcome>,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.outcomes.iter().all(|o| o.failure.is_none())
+    }
+}
+
+impl core::fmt::Display for Report {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        for outcome in &self.outcomes {
+            match &outcome.failure {
+                None => writeln!(f, "PASS {} expect[{}]", self.name, outcome.index)?,
+                Some(why) => writeln!(
+                    f,
+                    "FAIL {} expect[{}] at {} ms: {why}",
+                    self.name, outcome.index, outcome.timestamp_ms
+                )?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Vehicle node with the scenario steps applied to what it sends
+struct ScriptedVehicle {
+    inner: FaultInjector<VehicleNode>,
+    steps: Vec<Step>,
+}
+
+impl Node for ScriptedVehicle {
+    fn receive(&mut self, now_ms: u64, frame: &SimFrame) {
+        self.inner.receive(now_ms, frame);
+    }
+
+    fn tick(&mut self, now_ms: u64, tx: &mut Vec<SimFrame>) {
+        let mut sent = Vec::new();
+        self.inner.tick(now_ms, &mut sent);
+        for mut frame in sent {
+            if let Ok(mut decoded) = Decoded::from_payload(frame.id, &frame.data) {
+                for step in self.steps.iter_mut().filter(|s| s.active(now_ms)) {
+                    step.rewrite(&mut decoded);
+                }
+                frame.data = decoded.to_payload().to_vec();
+            }
+            tx.push(frame);
+        }
+    }
+}
+
+/// Simulated bus step, expectations are checked after every step
+const STEP_MS: u64 = 10;
+
+impl Scenario {
+    pub fn from_toml(text: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(text)
+    }
+
+    fn end_ms(&self) -> u64 {
+        let steps = self.steps.iter().map(|s| s.until_ms.unwrap_or(s.at_ms));
+        let expectations = self.expectations.iter().map(Expect::deadline);
+        steps.chain(expectations).fold(self.duration_ms, u64::max)
+    }
+
+    pub fn run(&self) -> Report {
+        let mut chademo = Chademo::new(self.evse.max_amps);
+        chademo.set_evse_protocol(self.evse.protocol);
+        let mut evse = Evse::new(chademo);
+        evse.set_discharge_request(self.evse.discharge_request);
+
+        let ev = VehicleEmulator::new(self.vehicle.params(), self.vehicle.soc.unwrap_or(50.0));
+        let mut injector = FaultInjector::new(VehicleNode::new(ev));
+        for step in &self.steps {
+            let fault = match step.fault {
+                Some(FaultSpec::Silence) => Fault::Silence,
+                Some(FaultSpec::Dlc { id, len }) => Fault::Dlc { id, len },
+                None => continue,
+            };
+            injector = match step.until_ms {
+                Some(until) => injector.between(step.at_ms, until, fault),
+                None => injector.at(step.at_ms, fault),
+            };
+        }
+
+        let mut bus = SimBus::new(STEP_MS);
+        let evse_id = bus.add(EvseNode::new(evse));
+        bus.add(ScriptedVehicle {
+            inner: injector,
+            steps: self.steps.clone(),
+        });
+
+        let mut pending: Vec<usize> = (0..self.expectations.len()).collect();
+        let mut outcomes = Vec::new();
+        let end = self.end_ms();
+        while bus.now() <= end {
+            let now = bus.now();
+            if self
+                .steps
+                .iter()
+                .any(|s| s.evse_stop && (now..now + STEP_MS).contains(&s.at_ms))
+            {
+                bus.node_mut::<EvseNode>(evse_id).evse.stop();
+            }
+            bus.step();
+            pending.retain(|&index| self.evaluate(index, &bus, evse_id, now, &mut outcomes));
+        }
+        outcomes.sort_by_key(|o| o.index);
+        Report {
+            name: self.name.clone(),
+            outcomes,
+        }
+    }
+
+    /// Record the outcome of expectation `index` once decided, true while still pending
+    fn evaluate(
+        &self,
+        index: usize,
+        bus: &SimBus,
+        evse: NodeId,
+        now: u64,
+        outcomes: &mut Vec<Outcome>,
+    ) -> bool {
+        let expect = &self.expectations[index];
+        let failure = expect.check(bus.node::<EvseNode>(evse));
+        let decided = match (expect.at_ms, expect.by_ms) {
+            (Some(at), _) => now >= at,
+            (None, Some(by)) => failure.is_none() || now >= by,
+            (None, None) => true,
+        };
+        if decided {
+            outcomes.push(Outcome {
+                index,
+                timestamp_ms: now,
+                failure,
+            });
+        }
+        !decided
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn scenario_files_test() {
+        let files = [
+            include_str!("../scenarios/normal-charge.toml"),
+            include_str!("../scenarios/vehicle-stop-mid-charge.toml"),
+            include_str!("../scenarios/communication-loss.toml"),
+        ];
+        for text in files {
+            let report = Scenario::from_toml(text).unwrap().run();
+            assert!(report.passed(), "{report}");
+        }
+    }
+
+    #[test]
+    fn failure_report_test() {
+        let scenario = Scenario::from_toml(
+            r#"
+            name = "wrong expectation"
+            [[expect]]
+            at_ms = 3000
+            state = "Idle"
+            [[expect]]
+            by_ms = 3000
+            state = "Charging"
+            "#,
+        )
+        .unwrap();
+        let report = scenario.run();
+        assert!(!report.passed());
+        assert_eq!(
+            report.to_string(),
+            "FAIL wrong expectation expect[0] at 3000 ms: state Charging, expected Idle\n\
+             PASS wrong expectation expect[1]\n"
+        );
+        assert!(Scenario::from_toml("name = 'x'\n[vehicle]\nsock = 1").is_err());
+        let scenario = Scenario::from_toml("name = 'x'\n[evse]\nprotocol = 3").unwrap();
+        assert_eq!(scenario.evse.protocol, ProtocolNumber::V1_1);
+        let error = Scenario::from_toml("name = 'x'\n[evse]\nprotocol = 9").unwrap_err();
+        assert!(error.to_string().contains("protocol number 9"), "{error}");
+    }
+}
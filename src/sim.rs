@@ -0,0 +1,494 @@
+//! Deterministic in-memory CAN bus for session tests.
+//!
+//! [`SimBus`] steps a simulated millisecond clock and, on every step, ticks each [`Node`] in the
+//! order it was added and delivers the frames it sent to every other node. No wall clock or
+//! threads are involved, so whole sessions run in `cargo test` in milliseconds and repeat exactly.
+//! [`SimPowerStage`] stands in for the DC converter so the reported H'109/H'208 output follows
+//! the converter dynamics.
+use std::any::Any;
+use std::boxed::Box;
+use std::vec::Vec;
+
+use crate::emulator::VehicleEmulator;
+use crate::evse::{Evse, EvseState, PowerStage, Setpoint};
+use crate::trace::{Direction, TraceRecord};
+
+/// Frame on the simulated bus, `data` may be any length so bad DLCs can be sent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimFrame {
+    pub id: u16,
+    pub data: Vec<u8>,
+}
+
+impl SimFrame {
+    pub fn new(id: u16, data: &[u8]) -> Self {
+        Self {
+            id,
+            data: data.to_vec(),
+        }
+    }
+}
+
+/// Index of a node on the bus, returned by [`SimBus::add`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NodeId(pub usize);
+
+pub trait Node: Any {
+    /// Frame sent by another node during the current step
+    fn receive(&mut self, now_ms: u64, frame: &SimFrame);
+    /// Advance to `now_ms` and queue frames to send in `tx`
+    fn tick(&mut self, now_ms: u64, tx: &mut Vec<SimFrame>);
+}
+
+/// Frame as it went over the bus
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sent {
+    pub timestamp_ms: u64,
+    pub from: NodeId,
+    pub frame: SimFrame,
+}
+
+impl From<&Sent> for TraceRecord {
+    fn from(sent: &Sent) -> Self {
+        let len = sent.frame.data.len().min(8);
+        let mut data = [0; 8];
+        data[..len].copy_from_slice(&sent.frame.data[..len]);
+        Self {
+            timestamp: sent.timestamp_ms * 1000,
+            id: sent.frame.id as u32,
+            dir: Direction::Rx,
+            len: len as u8,
+            data,
+            ..Default::default()
+        }
+    }
+}
+
+pub struct SimBus {
+    now_ms: u64,
+    step_ms: u64,
+    nodes: Vec<Box<dyn Node>>,
+    log: Vec<Sent>,
+}
+
+impl SimBus {
+    pub fn new(step_ms: u64) -> Self {
+        Self {
+            now_ms: 0,
+            step_ms,
+            nodes: Vec::new(),
+            log: Vec::new(),
+        }
+    }
+
+    pub fn add<N: Node>(&mut self, node: N) -> NodeId {
+        self.nodes.push(Box::new(node));
+        NodeId(self.nodes.len() - 1)
+    }
+
+    /// Panics when `id` is not a node of type `N`
+    pub fn node<N: Node>(&self, id: NodeId) -> &N {
+        let node: &dyn Any = self.nodes[id.0].as_ref();
+        node.downcast_ref().expect("node type")
+    }
+
+    /// Panics when `id` is not a node of type `N`
+    pub fn node_mut<N: Node>(&mut self, id: NodeId) -> &mut N {
+        let node: &mut dyn Any = self.nodes[id.0].as_mut();
+        node.downcast_mut().expect("node type")
+    }
+
+    pub fn now(&self) -> u64 {
+        self.now_ms
+    }
+
+    /// Every frame sent so far, in bus order
+    pub fn log(&self) -> &[Sent] {
+        &self.log
+    }
+
+    /// Tick every node at the current time, deliver what they sent, then advance the clock
+    pub fn step(&mut self) {
+        let now = self.now_ms;
+        let mut tx = Vec::new();
+        for i in 0..self.nodes.len() {
+            self.nodes[i].tick(now, &mut tx);
+            for frame in tx.drain(..) {
+                for (j, node) in self.nodes.iter_mut().enumerate() {
+                    if j != i {
+                        node.receive(now, &frame);
+                    }
+                }
+                self.log.push(Sent {
+                    timestamp_ms: now,
+                    from: NodeId(i),
+                    frame,
+                });
+            }
+        }
+        self.now_ms += self.step_ms;
+    }
+
+    pub fn run_for(&mut self, ms: u64) {
+        let until = self.now_ms + ms;
+        while self.now_ms < until {
+            self.step();
+        }
+    }
+
+    /// Step until `done` holds or `timeout_ms` of simulated time has passed, true if `done` held
+    pub fn run_until(&mut self, timeout_ms: u64, mut done: impl FnMut(&SimBus) -> bool) -> bool {
+        let until = self.now_ms + timeout_ms;
+        while self.now_ms < until {
+            if done(self) {
+                return true;
+            }
+            self.step();
+        }
+        done(self)
+    }
+}
+
+/// DC converter with first-order dynamics into a battery load
+///
+/// The output current slews towards the setpoint, then follows it with a first-order lag.
+/// [`SimPowerStage::ideal`] reaches the setpoint on the next step, like a perfect converter.
+#[derive(Debug, Clone, Copy)]
+pub struct SimPowerStage {
+    /// Output current slew rate limit in A/s
+    pub slew_rate: f32,
+    /// First-order time constant in ms
+    pub time_constant_ms: f32,
+    pub max_voltage: f32,
+    pub max_current: f32,
+    /// Conversion efficiency between grid and output, 0.0-1.0
+    pub efficiency: f32,
+    /// Peak uniform measurement noise as `(V, A)`
+    pub noise: (f32, f32),
+    /// Battery EMF at the output, `None` holds the output at the setpoint voltage
+    pub load_voltage: Option<f32>,
+    /// Series resistance of cable and battery in Ω
+    pub load_resistance: f32,
+    setpoint: Setpoint,
+    reference: f32,
+    voltage: f32,
+    current: f32,
+    last_ms: Option<u64>,
+    rng: u32,
+}
+
+impl SimPowerStage {
+    pub fn ideal() -> Self {
+        Self {
+            slew_rate: f32::INFINITY,
+            time_constant_ms: 0.0,
+            max_voltage: f32::INFINITY,
+            max_current: f32::INFINITY,
+            efficiency: 1.0,
+            noise: (0.0, 0.0),
+            load_voltage: None,
+            load_resistance: 0.0,
+            setpoint: Setpoint::default(),
+            reference: 0.0,
+            voltage: 0.0,
+            current: 0.0,
+            last_ms: None,
+            rng: 0x2545_F491,
+        }
+    }
+
+    /// Power drawn from the grid in W, negative while feeding it
+    pub fn input_power(&self) -> f32 {
+        let output = self.voltage * self.current;
+        match output >= 0.0 {
+            true => output / self.efficiency,
+            false => output * self.efficiency,
+        }
+    }
+
+    /// Current the stage settles at for the present setpoint
+    fn target_current(&self) -> f32 {
+        if !self.setpoint.enabled {
+            return 0.0;
+        }
+        let amps = self
+            .setpoint
+            .current
+            .clamp(-self.max_current, self.max_current);
+        match self.load_voltage {
+            // Constant voltage once the output reaches the setpoint voltage
+            Some(emf) if self.load_resistance > 0.0 => {
+                let volts = self.setpoint.voltage.min(self.max_voltage);
+                amps.min((volts - emf) / self.load_resistance)
+            }
+            _ => amps,
+        }
+    }
+
+    fn advance(&mut self, dt_ms: u64) {
+        let target = self.target_current();
+        self.reference = match self.slew_rate.is_finite() {
+            true => {
+                let step = self.slew_rate * dt_ms as f32 / 1000.0;
+                self.reference + (target - self.reference).clamp(-step, step)
+            }
+            false => target,
+        };
+        let alpha = match self.time_constant_ms > 0.0 {
+            true => dt_ms as f32 / (self.time_constant_ms + dt_ms as f32),
+            false => 1.0,
+        };
+        self.current += (self.reference - self.current) * alpha;
+        self.voltage = match (self.setpoint.enabled, self.load_voltage) {
+            (true, None) => self.setpoint.voltage.min(self.max_voltage),
+            (_, Some(emf)) if self.setpoint.enabled || self.current != 0.0 => {
+                emf + self.current * self.load_resistance
+            }
+            _ => 0.0,
+        };
+    }
+
+    /// Uniform in `-peak..peak` from a xorshift generator, so runs stay repeatable
+    fn noise(&mut self, peak: f32) -> f32 {
+        if peak == 0.0 {
+            return 0.0;
+        }
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        (self.rng as f32 / u32::MAX as f32 * 2.0 - 1.0) * peak
+    }
+}
+
+impl PowerStage for SimPowerStage {
+    /// The output moves towards `setpoint` over the time since the previous call
+    fn apply(&mut self, now_ms: u64, setpoint: Setpoint) {
+        let dt_ms = now_ms.saturating_sub(self.last_ms.unwrap_or(now_ms));
+        self.last_ms = Some(now_ms);
+        self.setpoint = setpoint;
+        self.advance(dt_ms);
+    }
+
+    fn measured(&mut self) -> (f32, f32) {
+        let (volts, amps) = self.noise;
+        let voltage = self.voltage + self.noise(volts);
+        let current = self.current + self.noise(amps);
+        (voltage, current)
+    }
+}
+
+/// [`Evse`] driving a [`SimPowerStage`], ideal unless built with [`EvseNode::with_stage`]
+pub struct EvseNode {
+    pub evse: Evse,
+    pub stage: SimPowerStage,
+    /// Last measured output as `(V, A)`
+    pub output: (f32, f32),
+    /// Decode errors seen, e.g. from injected bad DLCs
+    pub rx_errors: usize,
+}
+
+impl EvseNode {
+    pub fn new(evse: Evse) -> Self {
+        Self::with_stage(evse, SimPowerStage::ideal())
+    }
+
+    pub fn with_stage(evse: Evse, stage: SimPowerStage) -> Self {
+        Self {
+            evse,
+            stage,
+            output: (0.0, 0.0),
+            rx_errors: 0,
+        }
+    }
+
+    pub fn state(&self) -> EvseState {
+        self.evse.state()
+    }
+}
+
+impl Node for EvseNode {
+    fn receive(&mut self, now_ms: u64, frame: &SimFrame) {
+        let from_vehicle = matches!(frame.id, 0x100 | 0x101 | 0x102 | 0x200);
+        if from_vehicle && self.evse.receive(now_ms, frame.id, &frame.data).is_err() {
+            self.rx_errors += 1;
+        }
+    }
+
+    fn tick(&mut self, now_ms: u64, tx: &mut Vec<SimFrame>) {
+        let setpoint = self.evse.poll(now_ms, self.output.0, self.output.1);
+        self.stage.apply(now_ms, setpoint);
+        self.output = self.stage.measured();
+        if self.evse.tx_due(now_ms) {
+            let frames = self.evse.chademo.tx_payloads().into_iter();
+            tx.extend(frames.map(|(id, data)| SimFrame::new(id, &data)));
+        }
+    }
+}
+
+/// [`VehicleEmulator`] sending H'100/101/102/200 every 100 ms
+pub struct VehicleNode {
+    pub ev: VehicleEmulator,
+    last_tx_ms: Option<u64>,
+}
+
+impl VehicleNode {
+    pub const TX_PERIOD_MS: u64 = 100;
+
+    pub fn new(ev: VehicleEmulator) -> Self {
+        Self {
+            ev,
+            last_tx_ms: None,
+        }
+    }
+}
+
+impl Node for VehicleNode {
+    fn receive(&mut self, _now_ms: u64, frame: &SimFrame) {
+        // Frames of other vehicle side nodes are not for us
+        let _ = self.ev.receive(frame.id, &frame.data);
+    }
+
+    fn tick(&mut self, now_ms: u64, tx: &mut Vec<SimFrame>) {
+        self.ev.tick(now_ms);
+        if self
+            .last_tx_ms
+            .is_none_or(|last| now_ms - last >= Self::TX_PERIOD_MS)
+        {
+            self.last_tx_ms = Some(now_ms);
+            let frames = self.ev.tx_payloads().into_iter();
+            tx.extend(frames.map(|(id, data)| SimFrame::new(id, &data)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::compliance::ComplianceChecker;
+    use crate::emulator::{VehicleParams, VehicleState};
+    use crate::frames::ProtocolNumber;
+    use crate::Chademo;
+
+    fn session(params: VehicleParams, soc: f32) -> (SimBus, NodeId, NodeId) {
+        let mut bus = SimBus::new(10);
+        let mut chademo = Chademo::new(16);
+        chademo.set_evse_protocol(ProtocolNumber::V1_1);
+        let evse = bus.add(EvseNode::new(Evse::new(chademo)));
+        let ev = bus.add(VehicleNode::new(VehicleEmulator::new(params, soc)));
+        (bus, evse, ev)
+    }
+
+    fn assert_compliant(bus: &SimBus) {
+        let mut checker = ComplianceChecker::new();
+        for sent in bus.log() {
+            let frame = &sent.frame;
+            checker
+                .check(sent.timestamp_ms, frame.id, &frame.data, |v| panic!("{v}"))
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn charge_session_test() {
+        let params = VehicleParams {
+            target_soc: 75,
+            ..Default::default()
+        };
+        let (mut bus, evse, ev) = session(params, 73.5);
+        assert!(bus.run_until(30 * 60 * 1000, |bus| {
+            bus.node::<EvseNode>(evse).state() == EvseState::Finished
+        }));
+        let vehicle = &bus.node::<VehicleNode>(ev).ev;
+        assert_eq!(vehicle.state(), VehicleState::Finished);
+        assert!(vehicle.soc() >= 75.0);
+        assert_compliant(&bus);
+
+        // H'102 every 100 ms from the first step
+        let x102: Vec<_> = bus.log().iter().filter(|s| s.frame.id == 0x102).collect();
+        assert_eq!(x102[0].timestamp_ms, 0);
+        assert!(x102
+            .windows(2)
+            .all(|w| w[1].timestamp_ms - w[0].timestamp_ms == 100));
+        // Same inputs, same bus traffic
+        let (mut again, evse, _) = session(params, 73.5);
+        again.run_until(30 * 60 * 1000, |bus| {
+            bus.node::<EvseNode>(evse).state() == EvseState::Finished
+        });
+        assert_eq!(bus.log(), again.log());
+    }
+
+    #[test]
+    fn discharge_session_test() {
+        let params = VehicleParams {
+            max_discharge_current: 15,
+            min_discharge_soc: 30,
+            ..Default::default()
+        };
+        let (mut bus, evse, ev) = session(params, 30.5);
+        bus.node_mut::<EvseNode>(evse)
+            .evse
+            .set_discharge_request(10.0);
+        assert!(bus.run_until(5_000, |bus| { bus.node::<EvseNode>(evse).output.1 < 0.0 }));
+        // 200.0 limit unmasked once the contactors closed, 208.0 reports the drawn current
+        assert_eq!(bus.node::<EvseNode>(evse).output.1, -10.0);
+        bus.run_for(200);
+        assert_eq!(
+            bus.node::<VehicleNode>(ev)
+                .ev
+                .x208
+                .unwrap()
+                .get_discharge_current(),
+            10
+        );
+
+        assert!(bus.run_until(30 * 60 * 1000, |bus| {
+            bus.node::<EvseNode>(evse).state() == EvseState::Finished
+        }));
+        let vehicle = &bus.node::<VehicleNode>(ev).ev;
+        assert_eq!(vehicle.state(), VehicleState::Finished);
+        assert!(vehicle.soc() <= 30.0);
+        assert_compliant(&bus);
+        let record = TraceRecord::from(&bus.log()[0]);
+        assert_eq!(record.standard_id(), Some(0x108));
+    }
+
+    #[test]
+    fn power_stage_test() {
+        let stage = SimPowerStage {
+            slew_rate: 50.0,
+            time_constant_ms: 200.0,
+            efficiency: 0.95,
+            noise: (0.5, 0.1),
+            load_voltage: Some(370.0),
+            load_resistance: 0.1,
+            ..SimPowerStage::ideal()
+        };
+        let mut bus = SimBus::new(10);
+        let evse = bus.add(EvseNode::with_stage(Evse::new(Chademo::new(16)), stage));
+        let params = VehicleParams::default();
+        let ev = bus.add(VehicleNode::new(VehicleEmulator::new(params, 50.0)));
+        assert!(bus.run_until(3_000, |bus| {
+            bus.node::<EvseNode>(evse).state() == EvseState::Charging
+        }));
+
+        // Slew limited to 5 A in the first 100 ms, settled on 102.3 after 2 s
+        bus.run_for(100);
+        assert!(bus.node::<EvseNode>(evse).output.1 < 6.0);
+        bus.run_for(2_000);
+        let node = bus.node::<EvseNode>(evse);
+        assert!((node.output.1 - 14.0).abs() < 0.2, "{:?}", node.output);
+        assert!((node.output.0 - 371.4).abs() < 0.6, "{:?}", node.output);
+        assert!((node.stage.input_power() - 371.4 * 14.0 / 0.95).abs() < 10.0);
+        // 109.1-3 carry the measured output
+        let x109 = bus.node::<VehicleNode>(ev).ev.x109.unwrap();
+        assert!((13..=14).contains(&x109.output_current));
+        assert_eq!(x109.output_voltage as u16, 371);
+
+        // 109.5.0 held until the output ramped below 5 A, checked by 109.5.0 compliance
+        bus.node_mut::<EvseNode>(evse).evse.stop();
+        assert!(bus.run_until(5_000, |bus| {
+            bus.node::<EvseNode>(evse).state() == EvseState::Finished
+        }));
+        assert_compliant(&bus);
+    }
+}
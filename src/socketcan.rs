@@ -0,0 +1,217 @@
+//! Linux SocketCAN transport for running [`crate::Chademo`] on a host against `can0`/`vcan0`.
+//!
+//! [`SocketCanFrame`] implements `embedded_can::Frame`, so it plugs into
+//! [`crate::eh1::ChademoFrames`] and [`crate::eh1::FrameCodec`] like any MCU driver frame.
+use std::ffi::CString;
+use std::io;
+use std::mem;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::time::Duration;
+
+use embedded_can::{ExtendedId, Frame, Id, StandardId};
+
+/// Classic CAN frame as read from and written to a raw CAN socket
+#[derive(Clone, Copy)]
+pub struct SocketCanFrame(libc::can_frame);
+
+impl core::fmt::Debug for SocketCanFrame {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_struct("SocketCanFrame")
+            .field("id", &self.id())
+            .field("data", &self.data())
+            .finish()
+    }
+}
+
+impl Frame for SocketCanFrame {
+    fn new(id: impl Into<Id>, data: &[u8]) -> Option<Self> {
+        if data.len() > 8 {
+            return None;
+        }
+        let can_id = match id.into() {
+            Id::Standard(id) => id.as_raw() as u32,
+            Id::Extended(id) => id.as_raw() | libc::CAN_EFF_FLAG,
+        };
+        // SAFETY: can_frame is plain old data, all zero is a valid empty frame
+        let mut frame: libc::can_frame = unsafe { mem::zeroed() };
+        frame.can_id = can_id;
+        frame.can_dlc = data.len() as u8;
+        frame.data[..data.len()].copy_from_slice(data);
+        Some(Self(frame))
+    }
+
+    fn new_remote(id: impl Into<Id>, dlc: usize) -> Option<Self> {
+        let mut frame = Self::new(id, &[])?;
+        if dlc > 8 {
+            return None;
+        }
+        frame.0.can_id |= libc::CAN_RTR_FLAG;
+        frame.0.can_dlc = dlc as u8;
+        Some(frame)
+    }
+
+    fn is_extended(&self) -> bool {
+        self.0.can_id & libc::CAN_EFF_FLAG != 0
+    }
+
+    fn is_remote_frame(&self) -> bool {
+        self.0.can_id & libc::CAN_RTR_FLAG != 0
+    }
+
+    fn id(&self) -> Id {
+        match self.is_extended() {
+            true => Id::Extended(ExtendedId::new(self.0.can_id & libc::CAN_EFF_MASK).unwrap()),
+            false => {
+                Id::Standard(StandardId::new((self.0.can_id & libc::CAN_SFF_MASK) as u16).unwrap())
+            }
+        }
+    }
+
+    fn dlc(&self) -> usize {
+        self.0.can_dlc as usize
+    }
+
+    fn data(&self) -> &[u8] {
+        &self.0.data[..self.dlc().min(8)]
+    }
+}
+
+/// Raw CAN socket bound to a single interface
+#[derive(Debug)]
+pub struct CanSocket {
+    fd: OwnedFd,
+}
+
+impl CanSocket {
+    /// Open and bind a raw CAN socket on `ifname`, e.g. `vcan0`
+    pub fn open(ifname: &str) -> io::Result<Self> {
+        let name =
+            CString::new(ifname).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        // SAFETY: plain libc calls, every return value is checked
+        unsafe {
+            let ifindex = libc::if_nametoindex(name.as_ptr());
+            if ifindex == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let fd = libc::socket(libc::PF_CAN, libc::SOCK_RAW, libc::CAN_RAW);
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let fd = OwnedFd::from_raw_fd(fd);
+
+            let mut addr: libc::sockaddr_can = mem::zeroed();
+            addr.can_family = libc::AF_CAN as libc::sa_family_t;
+            addr.can_ifindex = ifindex as libc::c_int;
+            let ret = libc::bind(
+                fd.as_raw_fd(),
+                &addr as *const libc::sockaddr_can as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_can>() as libc::socklen_t,
+            );
+            if ret < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Self { fd })
+        }
+    }
+
+    /// Blocking reads return `ErrorKind::WouldBlock`/`TimedOut` after `timeout`
+    pub fn set_read_timeout(&self, timeout: Duration) -> io::Result<()> {
+        let tv = libc::timeval {
+            tv_sec: timeout.as_secs() as libc::time_t,
+            tv_usec: timeout.subsec_micros() as libc::suseconds_t,
+        };
+        // SAFETY: tv outlives the call and the length matches
+        let ret = unsafe {
+            libc::setsockopt(
+                self.fd.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_RCVTIMEO,
+                &tv as *const libc::timeval as *const libc::c_void,
+                mem::size_of::<libc::timeval>() as libc::socklen_t,
+            )
+        };
+        match ret {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+
+    pub fn read_frame(&self) -> io::Result<SocketCanFrame> {
+        // SAFETY: all zero is a valid can_frame, read writes at most its size
+        let mut frame: libc::can_frame = unsafe { mem::zeroed() };
+        let size = mem::size_of::<libc::can_frame>();
+        let ret = unsafe {
+            libc::read(
+                self.fd.as_raw_fd(),
+                &mut frame as *mut libc::can_frame as *mut libc::c_void,
+                size,
+            )
+        };
+        match ret {
+            n if n < 0 => Err(io::Error::last_os_error()),
+            n if n as usize != size => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "short CAN frame read",
+            )),
+            _ => Ok(SocketCanFrame(frame)),
+        }
+    }
+
+    pub fn write_frame(&self, frame: &SocketCanFrame) -> io::Result<()> {
+        let size = mem::size_of::<libc::can_frame>();
+        // SAFETY: frame is a valid can_frame of `size` bytes
+        let ret = unsafe {
+            libc::write(
+                self.fd.as_raw_fd(),
+                &frame.0 as *const libc::can_frame as *const libc::c_void,
+                size,
+            )
+        };
+        match ret {
+            n if n < 0 => Err(io::Error::last_os_error()),
+            n if n as usize != size => Err(io::Error::new(
+                io::ErrorKind::WriteZero,
+                "short CAN frame write",
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl AsRawFd for CanSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::eh1::{ChademoFrames, FrameCodec};
+    use crate::frames::*;
+    use crate::Chademo;
+
+    #[test]
+    fn frame_test() {
+        let x102 = X102::from_payload(&[0x2, 0x9a, 0x1, 0x0, 0x0, 0xc8, 0x56, 0x0]).unwrap();
+        let frame: SocketCanFrame = x102.to_can().unwrap();
+        assert_eq!(frame.id(), Id::Standard(StandardId::new(0x102).unwrap()));
+        assert!(!frame.is_extended());
+        assert_eq!(frame.dlc(), 8);
+        assert_eq!(frame.data(), &x102.to_payload());
+        assert_eq!(
+            X102::from_can(&frame).unwrap().to_payload(),
+            x102.to_payload()
+        );
+
+        let mut chademo = Chademo::new(16);
+        chademo.decode(frame).unwrap();
+        assert_eq!(chademo.x102.state_of_charge, 0x56);
+        assert!(chademo.tx_frames::<SocketCanFrame>()[0].is_some());
+
+        let ext = SocketCanFrame::new(ExtendedId::new(0x102).unwrap(), &[0; 8]).unwrap();
+        assert!(ext.is_extended());
+        assert!(chademo.decode(ext).is_err());
+        assert!(SocketCanFrame::new(StandardId::new(0x102).unwrap(), &[0; 9]).is_none());
+    }
+}
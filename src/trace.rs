@@ -0,0 +1,203 @@
+//! CAN trace lines in the CSV layout of `CanLogs/`
+//!
+//! `Time Stamp,ID,Extended,Dir,Bus,LEN,D1,D2,D3,D4,D5,D6,D7,D8` with a microsecond time stamp
+//! and hex ID/data, e.g. `3036499,00000102,false,Rx,0,8,02,9A,01,00,00,C8,03,00,`
+use crate::error::ChademoError;
+
+pub const HEADER: &str = "Time Stamp,ID,Extended,Dir,Bus,LEN,D1,D2,D3,D4,D5,D6,D7,D8";
+
+/// Size of [`TraceRecord::to_bytes`]
+pub const RAW_LEN: usize = 23;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    #[default]
+    Rx,
+    Tx,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TraceRecord {
+    /// Microseconds
+    pub timestamp: u64,
+    pub id: u32,
+    pub extended: bool,
+    pub dir: Direction,
+    pub bus: u8,
+    pub len: u8,
+    pub data: [u8; 8],
+}
+
+impl TraceRecord {
+    pub fn new(timestamp: u64, id: u16, data: [u8; 8]) -> Self {
+        Self {
+            timestamp,
+            id: id as u32,
+            len: 8,
+            data,
+            ..Default::default()
+        }
+    }
+
+    pub fn timestamp_ms(&self) -> u64 {
+        self.timestamp / 1000
+    }
+
+    /// Standard CAN ID, `None` for extended frames
+    pub fn standard_id(&self) -> Option<u16> {
+        match self.extended {
+            false => u16::try_from(self.id).ok(),
+            true => None,
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data[..(self.len as usize).min(8)]
+    }
+
+    /// Fixed size binary form: time stamp and ID little endian, flags, bus, length and data
+    pub fn to_bytes(&self) -> [u8; RAW_LEN] {
+        let mut raw = [0u8; RAW_LEN];
+        raw[0..8].copy_from_slice(&self.timestamp.to_le_bytes());
+        raw[8..12].copy_from_slice(&self.id.to_le_bytes());
+        raw[12] = self.extended as u8 | ((self.dir == Direction::Tx) as u8) << 1;
+        raw[13] = self.bus;
+        raw[14] = self.len;
+        raw[15..].copy_from_slice(&self.data);
+        raw
+    }
+
+    pub fn from_bytes(raw: &[u8; RAW_LEN]) -> Self {
+        Self {
+            timestamp: u64::from_le_bytes(raw[0..8].try_into().unwrap()),
+            id: u32::from_le_bytes(raw[8..12].try_into().unwrap()),
+            extended: raw[12] & 1 != 0,
+            dir: match raw[12] & 2 {
+                0 => Direction::Rx,
+                _ => Direction::Tx,
+            },
+            bus: raw[13],
+            len: raw[14].min(8),
+            data: raw[15..].try_into().unwrap(),
+        }
+    }
+
+    /// Parse one line, errors carry the zero based column that failed
+    pub fn parse(line: &str) -> Result<Self, ChademoError> {
+        let mut fields = line.trim().split(',').map(str::trim);
+        let mut col = 0;
+        let mut next = || {
+            col += 1;
+            match fields.next() {
+                Some(field) if !field.is_empty() => Ok((col - 1, field)),
+                _ => Err(ChademoError::TraceParse(col - 1)),
+            }
+        };
+        let bad = ChademoError::TraceParse;
+
+        let (col, field) = next()?;
+        let timestamp = field.parse().map_err(|_| bad(col))?;
+        let (col, field) = next()?;
+        let id = u32::from_str_radix(field, 16).map_err(|_| bad(col))?;
+        let (col, field) = next()?;
+        let extended = field.parse().map_err(|_| bad(col))?;
+        let (col, field) = next()?;
+        let dir = match field {
+            "Rx" => Direction::Rx,
+            "Tx" => Direction::Tx,
+            _ => return Err(bad(col)),
+        };
+        let (col, field) = next()?;
+        let bus = field.parse().map_err(|_| bad(col))?;
+        let (col, field) = next()?;
+        let len = match field.parse() {
+            Ok(len @ 0..=8) => len,
+            _ => return Err(bad(col)),
+        };
+        let mut data = [0u8; 8];
+        for byte in data.iter_mut().take(len as usize) {
+            let (col, field) = next()?;
+            *byte = u8::from_str_radix(field, 16).map_err(|_| bad(col))?;
+        }
+        Ok(Self {
+            timestamp,
+            id,
+            extended,
+            dir,
+            bus,
+            len,
+            data,
+        })
+    }
+}
+
+/// Same layout as [`TraceRecord::parse`] reads, including the trailing comma
+impl core::fmt::Display for TraceRecord {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let dir = match self.dir {
+            Direction::Rx => "Rx",
+            Direction::Tx => "Tx",
+        };
+        write!(
+            f,
+            "{},{:08X},{},{},{},{},",
+            self.timestamp, self.id, self.extended, dir, self.bus, self.len
+        )?;
+        for byte in self.data() {
+            write!(f, "{byte:02X},")?;
+        }
+        Ok(())
+    }
+}
+
+/// Records of a whole trace, skipping the header and blank lines
+pub fn records(trace: &str) -> impl Iterator<Item = Result<TraceRecord, ChademoError>> + '_ {
+    trace
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with("Time Stamp"))
+        .map(TraceRecord::parse)
+}
+
+/// Write `records` as a trace [`records`] reads back, header first
+pub fn write_csv<'a, W: core::fmt::Write>(
+    out: &mut W,
+    records: impl IntoIterator<Item = &'a TraceRecord>,
+) -> core::fmt::Result {
+    writeln!(out, "{HEADER}")?;
+    for record in records {
+        writeln!(out, "{record}")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_test() {
+        let line = "3036499,00000102,false,Rx,0,8,02,9A,01,00,00,C8,03,00,";
+        let record = TraceRecord::parse(line).unwrap();
+        assert_eq!(record.timestamp_ms(), 3036);
+        assert_eq!(record.standard_id(), Some(0x102));
+        assert_eq!(record.dir, Direction::Rx);
+        assert_eq!(
+            record.data(),
+            &[0x02, 0x9A, 0x01, 0x00, 0x00, 0xC8, 0x03, 0x00]
+        );
+        assert_eq!(record.to_string(), line);
+        assert_eq!(TraceRecord::from_bytes(&record.to_bytes()), record);
+
+        assert!(matches!(
+            TraceRecord::parse("3036499,00000102,false,Up,0,8,02,9A,01,00,00,C8,03,00,"),
+            Err(ChademoError::TraceParse(3))
+        ));
+        assert!(matches!(
+            TraceRecord::parse("3036499,00000102,false,Rx,0,8,02,9A"),
+            Err(ChademoError::TraceParse(8))
+        ));
+
+        let log = include_str!("../CanLogs/nissan-leaf-chademo-start-stop.csv");
+        assert_eq!(records(log).filter(|r| r.is_ok()).count(), 4072);
+    }
+}